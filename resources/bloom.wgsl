@@ -0,0 +1,32 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@group(1) @binding(0)
+var t: texture_2d<f32>;
+
+@group(1) @binding(1)
+var s: sampler;
+
+const RADIUS: i32 = 4;
+const SPREAD: f32 = 2.0;
+const SIGMA: f32 = 2.5;
+const STRENGTH: f32 = 1.6;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = SPREAD / vec2<f32>(textureDimensions(t));
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var x = -RADIUS; x <= RADIUS; x = x + 1) {
+        for (var y = -RADIUS; y <= RADIUS; y = y + 1) {
+            let offset = vec2<f32>(f32(x), f32(y));
+            let weight = exp(-dot(offset, offset) / (2.0 * SIGMA * SIGMA));
+            sum = sum + textureSampleLevel(t, s, in.uv + offset * texel, 0.0) * weight;
+            total = total + weight;
+        }
+    }
+    return sum / total * STRENGTH * in.color;
+}
@@ -0,0 +1,32 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+@group(1) @binding(0)
+var t: texture_2d<f32>;
+
+@group(1) @binding(1)
+var s: sampler;
+
+const CURVATURE: f32 = 0.06;
+const SCANLINES: f32 = 450.0;
+const SCANLINE_STRENGTH: f32 = 0.2;
+const VIGNETTE: f32 = 0.3;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let centered = in.uv * 2.0 - 1.0;
+    let warped = centered + centered * centered.yx * centered.yx * CURVATURE;
+    let uv = warped * 0.5 + 0.5;
+
+    let colour = textureSample(t, s, uv);
+    let inside = all(uv >= vec2<f32>(0.0)) && all(uv <= vec2<f32>(1.0));
+
+    let scanline = 1.0 - SCANLINE_STRENGTH * (0.5 + 0.5 * sin(uv.y * SCANLINES * 6.2831853));
+    let vignette = clamp(1.0 - dot(centered, centered) * VIGNETTE, 0.0, 1.0);
+
+    let rgb = select(vec3<f32>(0.0), colour.rgb * scanline * vignette, inside);
+    return vec4<f32>(rgb, 1.0) * in.color;
+}
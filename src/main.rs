@@ -1,22 +1,40 @@
-use std::f32::consts::TAU;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::f32::consts::{PI, SQRT_2, TAU};
+use std::io::{Read, Write};
 use std::iter;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-use ggez::conf::{WindowMode, WindowSetup};
+use ggez::conf::{FullscreenType, WindowMode, WindowSetup};
+use ggez::audio::{SoundSource, Source};
 use ggez::event;
-use ggez::graphics::{self, Color, DrawParam, Image, Mesh};
-use ggez::input::keyboard::KeyCode;
+use ggez::graphics::{self, BlendMode, Color, DrawMode, DrawParam, Image, ImageEncodingFormat, ImageFormat, InstanceArray, Mesh, Rect, ScreenImage, Shader, ShaderBuilder, Text};
+use ggez::input::gamepad::gilrs::{Axis, Button};
+use ggez::input::keyboard::{KeyCode, KeyInput};
+use ggez::input::mouse::MouseButton;
 use ggez::mint::Point2;
-use ggez::{Context, GameResult};
+use ggez::{Context, GameError, GameResult};
 use ggez::glam::*;
 
+#[cfg(feature = "gravity")]
 use self_compare::SliceCompareExt;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 struct Obj {
     pos: Vec2,
     vel: Vec2,
     rot: f32,
     rot_v: f32,
+    size: u8,
+    radius: f32,
+    mass: f32,
+    flash: f32,
+    hp: u8,
+    age: f32,
     #[cfg(feature = "gravity")]
     grav_accel: Vec2,
 }
@@ -28,6 +46,12 @@ impl Obj {
             vel: Vec2::ZERO,
             rot: 0.,
             rot_v: 0.,
+            size: FULL_SIZE,
+            radius: OBJ_RADIUS,
+            mass: OBJ_MASS,
+            flash: 0.,
+            hp: 1,
+            age: 0.,
             #[cfg(feature = "gravity")]
             grav_accel: Vec2::ZERO,
         }
@@ -38,6 +62,12 @@ impl Obj {
             vel,
             rot,
             rot_v: 0.,
+            size: FULL_SIZE,
+            radius: OBJ_RADIUS,
+            mass: OBJ_MASS,
+            flash: 0.,
+            hp: 1,
+            age: 0.,
             #[cfg(feature = "gravity")]
             grav_accel: Vec2::ZERO
         }
@@ -48,6 +78,12 @@ impl Obj {
             vel: Vec2::new(vx, vy),
             rot,
             rot_v,
+            size: FULL_SIZE,
+            radius: OBJ_RADIUS,
+            mass: OBJ_MASS,
+            flash: 0.,
+            hp: 1,
+            age: 0.,
             #[cfg(feature = "gravity")]
             grav_accel: Vec2::ZERO
         }
@@ -55,7 +91,7 @@ impl Obj {
     fn draw_param(&self) -> DrawParam {
         DrawParam::new()
             .offset(Point2::from(Vec2::new(0.5, 0.5)))
-            .scale(Vec2::new(0.5, 0.5))
+            .scale(Vec2::splat(0.25 * self.size as f32))
             .dest(self.pos)
             .rotation(self.rot)
     }
@@ -63,24 +99,73 @@ impl Obj {
     pub const fn bullet(self, ttl: f32) -> Bullet {
         Bullet {
             obj: self,
+            prev: self.pos,
+            ttl,
+            kind: BulletKind::Normal,
+            trail: Trail::EMPTY,
+        }
+    }
+    pub const fn cluster(self, ttl: f32) -> Bullet {
+        Bullet {
+            obj: self,
+            prev: self.pos,
             ttl,
+            kind: BulletKind::Cluster,
+            trail: Trail::EMPTY,
         }
     }
-    pub fn pushed(self, dx: f32, dy: f32, dvx: f32, dvy: f32) -> Self {
+    pub fn pushed(self, rng: &mut StdRng, dx: f32, dy: f32, dvx: f32, dvy: f32) -> Self {
         Self {
             pos: self.pos + Vec2::new(dx, dy),
             vel: self.vel + Vec2::new(dvx, dvy),
-            rot: self.rot + rand::random_range(0. .. TAU),
-            rot_v: self.rot_v + rand::random_range(-3. .. 3.),
+            rot: self.rot + rng.random_range(0. .. TAU),
+            rot_v: self.rot_v + rng.random_range(CRATE_ROT_V_MIN .. CRATE_ROT_V_MAX),
+            size: self.size,
+            radius: self.radius,
+            mass: self.mass,
+            flash: 0.,
+            hp: 1,
+            age: self.age,
             #[cfg(feature = "gravity")]
             grav_accel: self.grav_accel,
         }
     }
-    fn resolve(&mut self, other: &mut Self) {
+    fn bounce_edges(&mut self, restitution: f32) {
+        const W: f32 = 16.;
+        if (self.pos.x < W && self.vel.x < 0.) || (self.pos.x >= (WIDTH-W) && self.vel.x > 0.) {
+            self.vel.x = -self.vel.x * restitution;
+        }
+        if (self.pos.y < W && self.vel.y < 0.) || (self.pos.y >= (HEIGHT-W) && self.vel.y > 0.) {
+            self.vel.y = -self.vel.y * restitution;
+        }
+    }
+    fn knock(&mut self, other: &mut Obj, wrap: bool) {
+        if self.resolve(other, wrap).is_some_and(|impact| impact > CRATE_FLASH_IMPACT) {
+            self.flash = CRATE_FLASH_TIME;
+            other.flash = CRATE_FLASH_TIME;
+        }
+    }
+    fn hit(&mut self, push: Vec2) -> bool {
+        self.hp = self.hp.saturating_sub(1);
+        if self.hp == 0 {
+            return false;
+        }
+        self.flash = CRATE_FLASH_TIME;
+        self.vel += CRATE_KNOCKBACK * self.inv_mass() * push;
+        true
+    }
+    fn inv_mass(&self) -> f32 {
+        if self.mass == 0. {
+            0.
+        } else {
+            1. / self.mass
+        }
+    }
+    fn resolve(&mut self, other: &mut Self, wrap: bool) -> Option<f32> {
         let a = self;
         let b = other;
 
-        let d = a.pos - b.pos;
+        let d = if wrap { wrapped_delta(a.pos, b.pos) } else { a.pos - b.pos };
         let dist_sq = d.length_squared();
 
         #[cfg(feature = "gravity")]
@@ -91,288 +176,3841 @@ impl Obj {
             b.grav_accel += GRAVITY_CONSTANT / dist_sq * d.normalize();
         }
 
-        const W: f32 = 32.;
-        if dist_sq < W * W {
+        let w = a.radius + b.radius;
+        if dist_sq < w * w {
             let dv = (a.vel - b.vel).dot(d) / dist_sq * d;
-            a.vel -= dv;
-            b.vel += dv;
+            let inv_sum = a.inv_mass() + b.inv_mass();
+            if inv_sum == 0. {
+                return Some(dv.length());
+            }
+            let (share_a, share_b) = (a.inv_mass() / inv_sum, b.inv_mass() / inv_sum);
+            a.vel -= 2. * share_a * dv;
+            b.vel += 2. * share_b * dv;
 
             let dist = dist_sq.sqrt();
-            let dp = 0.5 * (W / dist - 1.) * d;
-            a.pos += dp;
-            b.pos -= dp;
+            let dp = (w / dist - 1.) * d;
+            a.pos += share_a * dp;
+            b.pos -= share_b * dp;
+
+            Some(dv.length())
+        } else {
+            None
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum BulletKind {
+    Normal,
+    Cluster,
+    Shockwave,
+}
+
+impl BulletKind {
+    const fn recoil(self) -> f32 {
+        match self {
+            BulletKind::Normal => BULLET_RECOIL,
+            BulletKind::Cluster => CLUSTER_RECOIL,
+            BulletKind::Shockwave => SHOCKWAVE_RECOIL,
+        }
+    }
+    const fn color(self) -> Color {
+        match self {
+            BulletKind::Normal => BULLET_COLOR,
+            BulletKind::Cluster => CLUSTER_COLOR,
+            BulletKind::Shockwave => SHOCKWAVE_COLOR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Trail {
+    points: [Vec2; TRAIL_LEN],
+    head: usize,
+    len: usize,
+}
+
+impl Trail {
+    const EMPTY: Self = Trail { points: [Vec2::ZERO; TRAIL_LEN], head: 0, len: 0 };
+
+    fn push(&mut self, point: Vec2) {
+        self.points[self.head] = point;
+        self.head = (self.head + 1) % TRAIL_LEN;
+        self.len = (self.len + 1).min(TRAIL_LEN);
+    }
+    fn iter(&self) -> impl Iterator<Item = Vec2> + '_ {
+        (1 ..= self.len).map(|i| self.points[(self.head + TRAIL_LEN - i) % TRAIL_LEN])
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 struct Bullet {
     obj: Obj,
+    prev: Vec2,
     ttl: f32,
+    kind: BulletKind,
+    #[serde(skip)]
+    trail: Trail,
 }
 
 impl Bullet {
     fn draw_param(&self) -> DrawParam {
+        let color = self.kind.color();
         self.obj.draw_param()
-            .color(opacity(self.ttl.min(5.) * 2.))
+            .color(Color { a: color.a * fade_alpha(self.ttl, BULLET_FADE_TIME), ..color })
+    }
+    fn trail_params(&self, param: DrawParam) -> impl Iterator<Item = DrawParam> + '_ {
+        self.trail.iter().enumerate().map(move |(i, point)| {
+            let fade = 1. - (i + 1) as f32 / (TRAIL_LEN + 1) as f32;
+            param.dest(point).color(Color { a: param.color.a * fade * TRAIL_OPACITY, ..param.color })
+        })
     }
 }
 
-struct MainState {
-    ship: Obj,
-    bullets: Vec<Bullet>,
-    crates: Vec<Obj>,
-    splinters: Vec<Bullet>,
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Boss {
+    obj: Obj,
+    hp: f32,
+    fire_cooldown: f32,
+}
 
-    ship_img: Image,
-    crate_img: Image,
-    bullet_img: Image,
-    splinter_img: Image,
+impl Boss {
+    fn new(pos: Vec2) -> Self {
+        let obj = Obj { size: BOSS_SIZE, radius: BOSS_RADIUS, mass: BOSS_MASS, ..Obj::from(pos, Vec2::ZERO, 0.) };
+        Boss { obj, hp: BOSS_HP, fire_cooldown: BOSS_FIRE_INTERVAL }
+    }
+}
 
-    crate_spawn_time: f32,
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Missile {
+    obj: Obj,
+    ttl: f32,
+    target: Option<usize>,
+}
 
-    bounce_edge: bool,
-    show_vel: bool,
+impl Missile {
+    fn steer(&mut self, crates: &[Obj], wrap: bool, dt: f32) {
+        let delta = |crat: &Obj| if wrap { wrapped_delta(crat.pos, self.obj.pos) } else { crat.pos - self.obj.pos };
+        if self.target.is_none_or(|t| t >= crates.len()) {
+            self.target = (0..crates.len())
+                .min_by(|&a, &b| delta(&crates[a]).length_squared().total_cmp(&delta(&crates[b]).length_squared()));
+        }
+        if let Some(t) = self.target {
+            let d = delta(&crates[t]);
+            let turn = (d.y.atan2(d.x) - self.obj.rot + PI).rem_euclid(TAU) - PI;
+            self.obj.rot += turn.clamp(-MISSILE_TURN_RATE * dt, MISSILE_TURN_RATE * dt);
+        }
+        self.obj.vel = angle_to_vec(self.obj.rot) * MISSILE_SPEED;
+        self.ttl -= dt;
+    }
 }
 
-impl MainState {
-    fn new(ctx: &Context) -> GameResult<MainState> {
-        let s = MainState {
-            crate_spawn_time: -CRATE_SPAWN_RATE * 20.,
-            ship: Obj::new(0.5 * WIDTH, 0.5 * HEIGHT),
-            bullets: Vec::new(),
-            crates: Vec::new(),
-            splinters: Vec::new(),
-            ship_img: Image::from_path(ctx, "/ship.png").unwrap(),
-            crate_img: Image::from_path(ctx, "/crate.png").unwrap(),
-            bullet_img: Image::from_path(ctx, "/bullet.png").unwrap(),
-            splinter_img: Image::from_path(ctx, "/splinter.png").unwrap(),
-            bounce_edge: false,
-            show_vel: false,
-        };
-        Ok(s)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum PowerKind {
+    RapidFire,
+    TripleShot,
+    Shield,
+}
+
+impl PowerKind {
+    const ALL: [PowerKind; 3] = [PowerKind::RapidFire, PowerKind::TripleShot, PowerKind::Shield];
+
+    const fn color(self) -> Color {
+        match self {
+            PowerKind::RapidFire => RAPID_FIRE_COLOR,
+            PowerKind::TripleShot => TRIPLE_SHOT_COLOR,
+            PowerKind::Shield => SHIELD_COLOR,
+        }
     }
 }
 
-const CRATE_LIMIT: usize = 200;
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct PowerUp {
+    obj: Obj,
+    ttl: f32,
+    kind: PowerKind,
+}
 
-const ROT_SPEED: f32 = 5.53;
-const ACCELERATION: f32 = 150.;
-const CRATE_SPAWN_RATE: f32 = 0.65;
-const BULLET_SPEED: f32 = 470.;
-const CRATE_BULLET_COLLIDE_DIST: f32 = 16.+8.;
-#[cfg(feature = "gravity")]
-const GRAVITY_CONSTANT: f32 = 6.6743e-11 * 104e12 * 2.;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scene {
+    Menu,
+    Settings,
+    Playing,
+    GameOver,
+}
 
-pub fn angle_to_vec(angle: f32) -> Vec2 {
-    let (sin, cos) = angle.sin_cos();
-    Vec2::new(cos, sin)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SettingsRow {
+    Volume,
+    Difficulty,
+    Bounce,
+    Density,
 }
 
-impl event::EventHandler<ggez::GameError> for MainState {
-    fn update(&mut self, ctx: &mut Context) -> GameResult {
-        if self.crate_spawn_time <= 0. {
-            let x = rand::random_range(0. .. WIDTH);
-            let y = rand::random_range(0. .. HEIGHT);
-            
-            if (self.ship.pos-Vec2::new(x, y)).length_squared() >= 160.*160. {
-                self.crate_spawn_time += CRATE_SPAWN_RATE;
-                let obj = Obj::with(
-                    x, y,
-                    rand::random_range(-150. .. 150.),
-                    rand::random_range(-150. .. 150.),
-                    rand::random_range(0. .. TAU),
-                    rand::random_range(-3. .. 3.),
-                );
-                self.crates.push(obj);
-            }
+impl SettingsRow {
+    const ALL: [Self; 4] = [SettingsRow::Volume, SettingsRow::Difficulty, SettingsRow::Bounce, SettingsRow::Density];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sound {
+    Shoot,
+    Break,
+}
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Enemy {
+    obj: Obj,
+    fire_cooldown: f32,
+}
+
+impl Enemy {
+    fn steer(&mut self, target: Vec2, wrap: bool, dt: f32) {
+        let d = if wrap { wrapped_delta(target, self.obj.pos) } else { target - self.obj.pos };
+        self.obj.vel += d.normalize_or_zero() * ENEMY_ACCELERATION * dt;
+        self.obj.vel = self.obj.vel.clamp_length_max(ENEMY_MAX_SPEED);
+        if self.obj.vel != Vec2::ZERO {
+            self.obj.rot = self.obj.vel.y.atan2(self.obj.vel.x);
         }
+    }
+}
 
-        const DELTA: f32 = 1./60.;
-        if ctx.time.check_update_time(60) {
-            if self.crates.len() < CRATE_LIMIT {
-                self.crate_spawn_time -= DELTA;
-            }
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Magnet {
+    pos: Vec2,
+    ttl: f32,
+}
 
-            let mut deads = Vec::new();
-            for (i, bullet) in self.bullets.iter_mut().enumerate() {
-                bullet.ttl -= DELTA;
-                if bullet.ttl <= 0. {
-                    deads.push(i);
-                }
-            }
-            deads.drain(..).rev().for_each(|i| {self.bullets.remove(i);});
-            for (i, bullet) in self.splinters.iter_mut().enumerate() {
-                bullet.ttl -= DELTA;
-                if bullet.ttl <= 0. {
-                    deads.push(i);
-                }
-            }
-            deads.into_iter().rev().for_each(|i| {self.splinters.remove(i);});
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
 
-            if ctx.keyboard.is_key_just_pressed(KeyCode::Space) {
-                let dir = angle_to_vec(self.ship.rot);
-                let obj = Obj::from(self.ship.pos + dir * 20., self.ship.vel + dir * BULLET_SPEED, self.ship.rot);
-                self.bullets.push(obj.bullet(rand::random_range(4.5 .. 6.2)));
-            }
-            if ctx.keyboard.is_key_just_pressed(KeyCode::C) {
-                self.crate_spawn_time -= CRATE_SPAWN_RATE;
-            }
-            if ctx.keyboard.is_key_just_pressed(KeyCode::B) {
-                self.bounce_edge = !self.bounce_edge;
-            }
-            if ctx.keyboard.is_key_just_pressed(KeyCode::V) {
-                self.show_vel = !self.show_vel;
-            }
+impl Difficulty {
+    const fn next(self) -> Self {
+        match self {
+            Difficulty::Easy => Difficulty::Normal,
+            Difficulty::Normal => Difficulty::Hard,
+            Difficulty::Hard => Difficulty::Easy,
+        }
+    }
+    const fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+    const ALL: [Self; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+    const SETTINGS: [DifficultySettings; 3] = [
+        DifficultySettings { spawn_rate_factor: 1.5, crate_speed: 100., crate_limit_factor: 0.6 },
+        DifficultySettings { spawn_rate_factor: 1., crate_speed: 150., crate_limit_factor: 1. },
+        DifficultySettings { spawn_rate_factor: 0.6, crate_speed: 210., crate_limit_factor: 1.5 },
+    ];
+    const fn settings(self) -> DifficultySettings {
+        Self::SETTINGS[self as usize]
+    }
+}
 
-            if ctx.keyboard.is_key_pressed(KeyCode::A) {
-                self.ship.rot -= ROT_SPEED * DELTA;
-            }
-            if ctx.keyboard.is_key_pressed(KeyCode::D) {
-                self.ship.rot += ROT_SPEED * DELTA;
-            }
-            
-            let mut wish_dir = Vec2::ZERO;
-            if ctx.keyboard.is_key_pressed(KeyCode::W) {
-                wish_dir.x += 1.;
-            }
-            if ctx.keyboard.is_key_pressed(KeyCode::S) {
-                wish_dir.x -= 1.;
-            }
-            if ctx.keyboard.is_key_pressed(KeyCode::E) {
-                wish_dir.y += 1.;
-            }
-            if ctx.keyboard.is_key_pressed(KeyCode::Q) {
-                wish_dir.y -= 1.;
-            }
-            let wish_dir = wish_dir.normalize_or_zero();
-            let dir = angle_to_vec(self.ship.rot);
+impl std::str::FromStr for Difficulty {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        Difficulty::ALL.into_iter().find(|d| d.name().eq_ignore_ascii_case(s)).ok_or(())
+    }
+}
 
-            if ctx.keyboard.is_key_pressed(KeyCode::LShift) {
-                let velocity_to_cancel = self.ship.vel - self.ship.vel.dot(dir).max(0.) * dir;
-                self.ship.vel -= velocity_to_cancel.normalize_or_zero() * ACCELERATION * DELTA;
-            }
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DifficultySettings {
+    spawn_rate_factor: f32,
+    crate_speed: f32,
+    crate_limit_factor: f32,
+}
 
-            if wish_dir != Vec2::ZERO {
-                let accel = dir.rotate(wish_dir) * ACCELERATION;
-                self.ship.vel += accel * DELTA;
-            }
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+struct Combo {
+    count: u32,
+    time: f32,
+}
+
+impl Combo {
+    fn multiplier(self) -> u32 {
+        (1 + self.count / COMBO_STEP).min(COMBO_MAX_MULTIPLIER)
+    }
+    fn kill(&mut self, points: u32) -> u32 {
+        self.count += 1;
+        self.time = COMBO_WINDOW;
+        points * self.multiplier()
+    }
+    fn tick(&mut self, dt: f32) {
+        self.time -= dt;
+        if self.time <= 0. {
+            *self = Combo::default();
         }
+    }
+}
 
-        let iter = iter::once(&mut self.ship)
-            .chain(self.bullets.iter_mut().map(|b| &mut b.obj))
-            .chain(&mut self.crates)
-            .chain(self.splinters.iter_mut().map(|b| &mut b.obj));
-        for obj in iter {
-            #[cfg(feature = "gravity")]
-            {
-                obj.grav_accel = Vec2::ZERO;
-            }
-            obj.pos += obj.vel * DELTA;
-            obj.rot += obj.rot_v * DELTA;
-            if self.bounce_edge {
-                const W: f32 = 16.;
-                if obj.pos.x < W {
-                    obj.vel.x = obj.vel.x.abs();
-                } else if obj.pos.x >= (WIDTH-W) {
-                    obj.vel.x = -obj.vel.x.abs();
-                }
-                if obj.pos.y < W {
-                    obj.vel.y = obj.vel.y.abs();
-                } else if obj.pos.y >= (HEIGHT-W) {
-                    obj.vel.y = -obj.vel.y.abs();
-                }
-            } else {
-                obj.pos.x = obj.pos.x.rem_euclid(WIDTH);
-                obj.pos.y = obj.pos.y.rem_euclid(HEIGHT);
-            }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum WeaponMode {
+    Single,
+    Spread,
+    Shotgun,
+}
+
+impl WeaponMode {
+    const fn next(self) -> Self {
+        match self {
+            WeaponMode::Single => WeaponMode::Spread,
+            WeaponMode::Spread => WeaponMode::Shotgun,
+            WeaponMode::Shotgun => WeaponMode::Single,
+        }
+    }
+    const fn name(self) -> &'static str {
+        match self {
+            WeaponMode::Single => "Single",
+            WeaponMode::Spread => "Spread",
+            WeaponMode::Shotgun => "Shotgun",
         }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+enum SpawnDistribution {
+    Uniform,
+    EdgeBiased,
+    Ring,
+}
+
+impl std::str::FromStr for SpawnDistribution {
+    type Err = ();
+    fn from_str(s: &str) -> Result<Self, ()> {
+        [SpawnDistribution::Uniform, SpawnDistribution::EdgeBiased, SpawnDistribution::Ring]
+            .into_iter()
+            .find(|d| format!("{d:?}") == s)
+            .ok_or(())
+    }
+}
 
-        let mut dead_bullets = Vec::new();
-        for (b, bullet) in self.bullets.iter().enumerate() {
-            let mut dead = None;
-            for (c, crat) in self.crates.iter().enumerate() {
-                let dist = bullet.obj.pos - crat.pos;
-                    if dist.length_squared() < CRATE_BULLET_COLLIDE_DIST * CRATE_BULLET_COLLIDE_DIST {
-                    dead = Some(c);
-                    break;
+impl SpawnDistribution {
+    fn sample(self, rng: &mut StdRng, ship: Vec2) -> Vec2 {
+        match self {
+            SpawnDistribution::Uniform => Vec2::new(rng.random_range(0. .. WIDTH), rng.random_range(0. .. HEIGHT)),
+            SpawnDistribution::EdgeBiased => {
+                let x = rng.random_range(0. .. WIDTH);
+                let y = rng.random_range(0. .. HEIGHT);
+                match rng.random_range(0 .. 4) {
+                    0 => Vec2::new(rng.random_range(0. .. SPAWN_EDGE_BAND), y),
+                    1 => Vec2::new(rng.random_range(WIDTH - SPAWN_EDGE_BAND .. WIDTH), y),
+                    2 => Vec2::new(x, rng.random_range(0. .. SPAWN_EDGE_BAND)),
+                    _ => Vec2::new(x, rng.random_range(HEIGHT - SPAWN_EDGE_BAND .. HEIGHT)),
                 }
             }
-            if let Some(c) = dead {
-                let mut crat = self.crates.remove(c);
-                const D: f32 = 8.;
-                const DV: f32 = 50.;
-                crat.vel += 0.4 * bullet.obj.vel;
-                self.splinters.push(crat.pushed(D, 0., DV, 0.).bullet(rand::random_range(1.6 .. 4.2)));
-                self.splinters.push(crat.pushed(-D, 0., -DV, 0.).bullet(rand::random_range(1.6 .. 4.2)));
-                self.splinters.push(crat.pushed(0., D, 0., DV).bullet(rand::random_range(1.6 .. 4.2)));
-                self.splinters.push(crat.pushed(0., -D,0., -DV).bullet(rand::random_range(1.6 .. 4.2)));
-                dead_bullets.push(b);
+            SpawnDistribution::Ring => {
+                let pos = ship + angle_to_vec(rng.random_range(0. .. TAU)) * SPAWN_RING_RADIUS;
+                Vec2::new(pos.x.rem_euclid(WIDTH), pos.y.rem_euclid(HEIGHT))
             }
         }
-        dead_bullets.into_iter().rev().for_each(|i| {self.bullets.remove(i);});
+    }
+}
 
-        self.crates.compare_self_mut(Obj::resolve);
-        self.crates.iter_mut().for_each(|c| self.ship.resolve(c));
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThrustResponse {
+    Linear,
+    Snappy,
+}
 
-        Ok(())
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Anchor {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+    Center,
+}
+
+impl Anchor {
+    fn place(self, offset: Vec2, size: Vec2) -> Vec2 {
+        match self {
+            Anchor::TopLeft => offset,
+            Anchor::TopRight => Vec2::new(WIDTH - size.x - offset.x, offset.y),
+            Anchor::BottomLeft => Vec2::new(offset.x, HEIGHT - size.y - offset.y),
+            Anchor::BottomRight => Vec2::new(WIDTH, HEIGHT) - size - offset,
+            Anchor::Center => 0.5 * (Vec2::new(WIDTH, HEIGHT) - size) + offset,
+        }
     }
+}
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        let mut canvas = graphics::Canvas::from_frame(ctx, Color::BLACK);
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct HudLayout {
+    score: (Anchor, Vec2),
+    difficulty: (Anchor, Vec2),
+    crates: (Anchor, Vec2),
+    inspector: (Anchor, Vec2),
+    diagnostics: (Anchor, Vec2),
+}
 
-        canvas.draw(&self.ship_img, self.ship.draw_param());
-        for bullet in &self.bullets {
-            canvas.draw(&self.bullet_img, bullet.draw_param());
-        }
-        for craet in &self.crates {
-            canvas.draw(&self.crate_img, craet.draw_param());
-            if self.show_vel {
-                let line = Mesh::new_line(ctx, &[craet.pos, craet.pos + craet.vel], 2., Color::BLUE)?;
-                canvas.draw(&line, DrawParam::new());
-                #[cfg(feature = "gravity")] {
-                    let grav_line = Mesh::new_line(ctx, &[craet.pos + craet.vel, craet.pos + craet.vel + craet.grav_accel], 2., Color::GREEN)?;
-                    canvas.draw(&grav_line, DrawParam::new());
-                }
+impl HudLayout {
+    const DEFAULT: Self = HudLayout {
+        score: (Anchor::TopLeft, Vec2::new(8., 8.)),
+        difficulty: (Anchor::TopRight, Vec2::new(8., 8.)),
+        crates: (Anchor::TopRight, Vec2::new(8., 32.)),
+        inspector: (Anchor::BottomLeft, Vec2::new(8., 8.)),
+        diagnostics: (Anchor::BottomRight, Vec2::new(8., 8.)),
+    };
+    const MIRRORED: Self = HudLayout {
+        score: (Anchor::TopRight, Vec2::new(8., 8.)),
+        difficulty: (Anchor::TopLeft, Vec2::new(8., 8.)),
+        crates: (Anchor::TopLeft, Vec2::new(8., 32.)),
+        inspector: (Anchor::BottomRight, Vec2::new(8., 8.)),
+        diagnostics: (Anchor::BottomLeft, Vec2::new(8., 8.)),
+    };
+    const PRESETS: &[Self] = &[Self::DEFAULT, Self::MIRRORED];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Palette {
+    name: &'static str,
+    velocity: Color,
+    gravity: Color,
+    magnet: Color,
+    highlight: Color,
+    threat: Color,
+    hud: Color,
+    shape_cues: bool,
+}
+
+impl Palette {
+    const DEFAULT: Self = Palette {
+        name: "Default",
+        velocity: Color::BLUE,
+        gravity: Color::GREEN,
+        magnet: Color::MAGENTA,
+        highlight: Color::YELLOW,
+        threat: Color::RED,
+        hud: Color::WHITE,
+        shape_cues: false,
+    };
+    // Based on the Okabe-Ito palette
+    const DEUTERANOPIA: Self = Palette {
+        name: "Deuteranopia",
+        velocity: Color::new(0.34, 0.71, 0.91, 1.),
+        gravity: Color::new(0.9, 0.62, 0., 1.),
+        magnet: Color::new(0.8, 0.47, 0.65, 1.),
+        highlight: Color::new(0.94, 0.89, 0.26, 1.),
+        threat: Color::new(0.9, 0.62, 0., 1.),
+        hud: Color::WHITE,
+        shape_cues: true,
+    };
+    const PROTANOPIA: Self = Palette {
+        name: "Protanopia",
+        velocity: Color::new(0., 0.45, 0.7, 1.),
+        gravity: Color::new(0.94, 0.89, 0.26, 1.),
+        magnet: Color::new(0.34, 0.71, 0.91, 1.),
+        highlight: Color::new(0.94, 0.89, 0.26, 1.),
+        threat: Color::new(0.94, 0.89, 0.26, 1.),
+        hud: Color::WHITE,
+        shape_cues: true,
+    };
+    const TRITANOPIA: Self = Palette {
+        name: "Tritanopia",
+        velocity: Color::new(0., 0.62, 0.45, 1.),
+        gravity: Color::new(0.8, 0.47, 0.65, 1.),
+        magnet: Color::new(0.84, 0.37, 0., 1.),
+        highlight: Color::WHITE,
+        threat: Color::new(0.84, 0.37, 0., 1.),
+        hud: Color::WHITE,
+        shape_cues: true,
+    };
+    const PRESETS: &[Self] = &[Self::DEFAULT, Self::DEUTERANOPIA, Self::PROTANOPIA, Self::TRITANOPIA];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Selection {
+    Ship,
+    Bullet(usize),
+    Crate(usize),
+    Splinter(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct Config {
+    rot_speed: f32,
+    acceleration: f32,
+    crate_spawn_rate: f32,
+    bullet_speed: f32,
+    max_ship_speed: f32,
+    crate_limit: usize,
+    spawn_distribution: SpawnDistribution,
+    density: f32,
+    width: f32,
+    height: f32,
+    volume: f32,
+    difficulty: Difficulty,
+    bounce: bool,
+}
+
+impl Config {
+    const DEFAULT: Self = Config {
+        rot_speed: ROT_SPEED,
+        acceleration: ACCELERATION,
+        crate_spawn_rate: CRATE_SPAWN_RATE,
+        bullet_speed: BULLET_SPEED,
+        max_ship_speed: MAX_SHIP_SPEED,
+        crate_limit: CRATE_LIMIT,
+        spawn_distribution: SPAWN_DISTRIBUTION,
+        density: DENSITY,
+        width: WIDTH,
+        height: HEIGHT,
+        volume: MASTER_VOLUME,
+        difficulty: Difficulty::Normal,
+        bounce: false,
+    };
+
+    fn load(ctx: &Context) -> Self {
+        let mut text = String::new();
+        let Ok(Ok(_)) = ctx.fs.open(CONFIG_PATH).map(|mut file| file.read_to_string(&mut text)) else {
+            return Config::DEFAULT;
+        };
+        match toml::from_str::<Config>(&text) {
+            Ok(config) => config.validated(),
+            Err(e) => {
+                eprintln!("could not parse {CONFIG_PATH}, using defaults: {e}");
+                Config::DEFAULT
             }
         }
-        for splinter in &self.splinters {
-            canvas.draw(&self.splinter_img, splinter.draw_param());
+    }
+    fn save(&self, ctx: &Context) -> GameResult<PathBuf> {
+        let text = toml::to_string(self).map_err(|e| GameError::CustomError(e.to_string()))?;
+        let mut file = ctx.fs.create(CONFIG_PATH)?;
+        file.write_all(text.as_bytes())?;
+        Ok(ctx.fs.user_config_dir().join(CONFIG_PATH.trim_start_matches('/')))
+    }
+    fn validated(self) -> Self {
+        let positive = |name: &str, value: f32, default: f32| if value.is_finite() && value > 0. {
+            value
+        } else {
+            eprintln!("config: {name} must be positive, using {default}");
+            default
+        };
+        let crate_limit = if (1 ..= MAX_CRATE_LIMIT).contains(&self.crate_limit) {
+            self.crate_limit
+        } else {
+            eprintln!("config: crate_limit must be between 1 and {MAX_CRATE_LIMIT}, using {CRATE_LIMIT}");
+            CRATE_LIMIT
+        };
+        let density = if (MIN_DENSITY ..= MAX_DENSITY).contains(&self.density) {
+            self.density
+        } else {
+            eprintln!("config: density must be between {MIN_DENSITY} and {MAX_DENSITY}, using {DENSITY}");
+            DENSITY
+        };
+        let volume = if (0. ..= 1.).contains(&self.volume) {
+            self.volume
+        } else {
+            eprintln!("config: volume must be between 0 and 1, using {MASTER_VOLUME}");
+            MASTER_VOLUME
+        };
+        Config {
+            rot_speed: positive("rot_speed", self.rot_speed, ROT_SPEED),
+            acceleration: positive("acceleration", self.acceleration, ACCELERATION),
+            crate_spawn_rate: positive("crate_spawn_rate", self.crate_spawn_rate, CRATE_SPAWN_RATE),
+            bullet_speed: positive("bullet_speed", self.bullet_speed, BULLET_SPEED),
+            max_ship_speed: positive("max_ship_speed", self.max_ship_speed, MAX_SHIP_SPEED),
+            crate_limit,
+            spawn_distribution: self.spawn_distribution,
+            density,
+            width: positive("width", self.width, WIDTH),
+            height: positive("height", self.height, HEIGHT),
+            volume,
+            difficulty: self.difficulty,
+            bounce: self.bounce,
         }
+    }
+}
 
-        canvas.finish(ctx)?;
-        Ok(())
+impl Config {
+    fn scaled_spawn_rate(&self) -> f32 {
+        self.crate_spawn_rate / self.density
+    }
+    fn scaled_crate_limit(&self) -> f32 {
+        self.crate_limit as f32 * self.density
+    }
+    fn splinters_per_crate(&self) -> usize {
+        ((SPLINTERS_PER_CRATE as f32 * self.density).round() as usize).max(1)
     }
 }
 
-const fn opacity(a: f32) -> Color {
-    Color {
-        a,
-        .. Color::WHITE
+impl Default for Config {
+    fn default() -> Self {
+        Config::DEFAULT
     }
 }
 
-const WIDTH: f32 = 1200.;
-const HEIGHT: f32 = 900.;
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Args {
+    width: Option<f32>,
+    height: Option<f32>,
+    seed: Option<u64>,
+    difficulty: Option<Difficulty>,
+    bounce: bool,
+    record: bool,
+    replay: bool,
+}
 
-pub fn main() -> GameResult {
-    let cb = ggez::ContextBuilder::new("shooty", "Falch")
-        .window_setup(WindowSetup::default().title("Shooty"))
-        .window_mode(WindowMode::default().dimensions(1200., 900.))
-    ;
-    let (ctx, event_loop) = cb.build()?;
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Option<Self> {
+        let mut parsed = Args::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => return None,
+                "--bounce" => parsed.bounce = true,
+                "--record" => parsed.record = true,
+                "--replay" => parsed.replay = true,
+                "--width" => parsed.width = parse_arg(&arg, args.next()),
+                "--height" => parsed.height = parse_arg(&arg, args.next()),
+                "--seed" => parsed.seed = parse_arg(&arg, args.next()),
+                "--difficulty" => parsed.difficulty = parse_arg(&arg, args.next()),
+                _ => eprintln!("ignoring unknown argument {arg:?}"),
+            }
+        }
+        Some(parsed)
+    }
+}
 
-    #[cfg(debug_assertions)]
-    {
-        // Add the workspace directory to the filesystem when running with cargo
-        if let Ok(manifest_dir) = ::std::env::var("CARGO_MANIFEST_DIR") {
-            let mut path = ::std::path::PathBuf::from(manifest_dir);
-            path.push("resources");
-            ctx.fs.mount(&path, true);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Controls {
+    forward: KeyCode,
+    backward: KeyCode,
+    strafe_left: KeyCode,
+    strafe_right: KeyCode,
+    rotate_left: KeyCode,
+    rotate_right: KeyCode,
+    brake: KeyCode,
+    fire: KeyCode,
+    fire_cluster: KeyCode,
+    fire_shockwave: KeyCode,
+    fire_missile: KeyCode,
+    bullet_time: KeyCode,
+    dash: KeyCode,
+    bomb: KeyCode,
+    spawn_crate: KeyCode,
+    cycle_difficulty: KeyCode,
+    cycle_weapon: KeyCode,
+    toggle_thrust_response: KeyCode,
+    toggle_bounce: KeyCode,
+    toggle_collect: KeyCode,
+    toggle_magnetic_edge: KeyCode,
+    toggle_auto_brake: KeyCode,
+    toggle_magnets: KeyCode,
+    toggle_spawning: KeyCode,
+    toggle_splinter_knockback: KeyCode,
+    toggle_panic_slow: KeyCode,
+    toggle_sub_stepping: KeyCode,
+    toggle_velocities: KeyCode,
+    toggle_fog: KeyCode,
+    toggle_trajectories: KeyCode,
+    toggle_aim_assist: KeyCode,
+    cycle_hud_layout: KeyCode,
+    cycle_palette: KeyCode,
+    toggle_debug: KeyCode,
+    toggle_diagnostics: KeyCode,
+    dump_input_log: KeyCode,
+    dump_state: KeyCode,
+    save_state: KeyCode,
+    load_state: KeyCode,
+    toggle_mouse_aim: KeyCode,
+    toggle_crt: KeyCode,
+    toggle_bloom: KeyCode,
+    toggle_follow_camera: KeyCode,
+    zoom_in: KeyCode,
+    zoom_out: KeyCode,
+    pause: KeyCode,
+    toggle_auto_resume: KeyCode,
+    confirm: KeyCode,
+    open_settings: KeyCode,
+    menu_up: KeyCode,
+    menu_down: KeyCode,
+    menu_left: KeyCode,
+    menu_right: KeyCode,
+    quit: KeyCode,
+    toggle_fullscreen: KeyCode,
+    toggle_photo_mode: KeyCode,
+    screenshot: KeyCode,
+    camera_left: KeyCode,
+    camera_right: KeyCode,
+    camera_up: KeyCode,
+    camera_down: KeyCode,
+}
+
+impl Controls {
+    const DEFAULT: Self = Controls {
+        forward: KeyCode::W,
+        backward: KeyCode::S,
+        strafe_left: KeyCode::Q,
+        strafe_right: KeyCode::E,
+        rotate_left: KeyCode::A,
+        rotate_right: KeyCode::D,
+        brake: KeyCode::LShift,
+        fire: KeyCode::Space,
+        fire_cluster: KeyCode::F,
+        fire_shockwave: KeyCode::T,
+        fire_missile: KeyCode::LControl,
+        bullet_time: KeyCode::LAlt,
+        dash: KeyCode::Capital,
+        bomb: KeyCode::Key1,
+        spawn_crate: KeyCode::C,
+        cycle_difficulty: KeyCode::Tab,
+        cycle_weapon: KeyCode::Y,
+        toggle_thrust_response: KeyCode::R,
+        toggle_bounce: KeyCode::B,
+        toggle_collect: KeyCode::X,
+        toggle_magnetic_edge: KeyCode::M,
+        toggle_auto_brake: KeyCode::Z,
+        toggle_magnets: KeyCode::H,
+        toggle_spawning: KeyCode::Key2,
+        toggle_splinter_knockback: KeyCode::Key3,
+        toggle_panic_slow: KeyCode::O,
+        toggle_sub_stepping: KeyCode::N,
+        toggle_velocities: KeyCode::V,
+        toggle_fog: KeyCode::G,
+        toggle_trajectories: KeyCode::L,
+        toggle_aim_assist: KeyCode::Semicolon,
+        cycle_hud_layout: KeyCode::U,
+        cycle_palette: KeyCode::K,
+        toggle_debug: KeyCode::F1,
+        toggle_diagnostics: KeyCode::F3,
+        dump_input_log: KeyCode::F8,
+        dump_state: KeyCode::F7,
+        save_state: KeyCode::F5,
+        load_state: KeyCode::F9,
+        toggle_mouse_aim: KeyCode::J,
+        toggle_crt: KeyCode::F2,
+        toggle_bloom: KeyCode::F4,
+        toggle_follow_camera: KeyCode::F6,
+        zoom_in: KeyCode::Equals,
+        zoom_out: KeyCode::Minus,
+        pause: KeyCode::P,
+        toggle_auto_resume: KeyCode::I,
+        confirm: KeyCode::Return,
+        open_settings: KeyCode::Comma,
+        menu_up: KeyCode::Up,
+        menu_down: KeyCode::Down,
+        menu_left: KeyCode::Left,
+        menu_right: KeyCode::Right,
+        quit: KeyCode::Escape,
+        toggle_fullscreen: KeyCode::F11,
+        toggle_photo_mode: KeyCode::F10,
+        screenshot: KeyCode::F12,
+        camera_left: KeyCode::Left,
+        camera_right: KeyCode::Right,
+        camera_up: KeyCode::Up,
+        camera_down: KeyCode::Down,
+    };
+
+    fn held(&self) -> [(KeyCode, u32); 8] {
+        [
+            (self.forward, Input::FORWARD),
+            (self.backward, Input::BACKWARD),
+            (self.strafe_left, Input::STRAFE_LEFT),
+            (self.strafe_right, Input::STRAFE_RIGHT),
+            (self.rotate_left, Input::ROTATE_LEFT),
+            (self.rotate_right, Input::ROTATE_RIGHT),
+            (self.brake, Input::BRAKE),
+            (self.fire, Input::FIRE_HELD),
+        ]
+    }
+    fn pressed(&self) -> [(KeyCode, u32); 20] {
+        [
+            (self.fire, Input::FIRE),
+            (self.fire_cluster, Input::FIRE_CLUSTER),
+            (self.fire_shockwave, Input::FIRE_SHOCKWAVE),
+            (self.fire_missile, Input::FIRE_MISSILE),
+            (self.bullet_time, Input::BULLET_TIME),
+            (self.dash, Input::DASH),
+            (self.bomb, Input::BOMB),
+            (self.spawn_crate, Input::SPAWN_CRATE),
+            (self.cycle_difficulty, Input::CYCLE_DIFFICULTY),
+            (self.cycle_weapon, Input::CYCLE_WEAPON),
+            (self.toggle_thrust_response, Input::TOGGLE_THRUST_RESPONSE),
+            (self.toggle_bounce, Input::TOGGLE_BOUNCE),
+            (self.toggle_collect, Input::TOGGLE_COLLECT),
+            (self.toggle_magnetic_edge, Input::TOGGLE_MAGNETIC_EDGE),
+            (self.toggle_auto_brake, Input::TOGGLE_AUTO_BRAKE),
+            (self.toggle_magnets, Input::TOGGLE_MAGNETS),
+            (self.toggle_spawning, Input::TOGGLE_SPAWNING),
+            (self.toggle_splinter_knockback, Input::TOGGLE_SPLINTER_KNOCKBACK),
+            (self.toggle_panic_slow, Input::TOGGLE_PANIC_SLOW),
+            (self.toggle_sub_stepping, Input::TOGGLE_SUB_STEPPING),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Input(u32);
+
+impl Input {
+    const FORWARD: u32 = 1 << 0;
+    const BACKWARD: u32 = 1 << 1;
+    const STRAFE_LEFT: u32 = 1 << 2;
+    const STRAFE_RIGHT: u32 = 1 << 3;
+    const ROTATE_LEFT: u32 = 1 << 4;
+    const ROTATE_RIGHT: u32 = 1 << 5;
+    const BRAKE: u32 = 1 << 6;
+    const FIRE: u32 = 1 << 7;
+    const FIRE_CLUSTER: u32 = 1 << 8;
+    const FIRE_SHOCKWAVE: u32 = 1 << 9;
+    const SPAWN_CRATE: u32 = 1 << 10;
+    const CYCLE_DIFFICULTY: u32 = 1 << 11;
+    const TOGGLE_THRUST_RESPONSE: u32 = 1 << 12;
+    const TOGGLE_BOUNCE: u32 = 1 << 13;
+    const TOGGLE_COLLECT: u32 = 1 << 14;
+    const TOGGLE_MAGNETIC_EDGE: u32 = 1 << 15;
+    const TOGGLE_AUTO_BRAKE: u32 = 1 << 16;
+    const TOGGLE_MAGNETS: u32 = 1 << 17;
+    const FIRE_HELD: u32 = 1 << 18;
+    const TOGGLE_PANIC_SLOW: u32 = 1 << 19;
+    const TOGGLE_SUB_STEPPING: u32 = 1 << 20;
+    const CYCLE_WEAPON: u32 = 1 << 21;
+    const FIRE_MISSILE: u32 = 1 << 22;
+    const BULLET_TIME: u32 = 1 << 23;
+    const DASH: u32 = 1 << 24;
+    const BOMB: u32 = 1 << 25;
+    const TOGGLE_SPAWNING: u32 = 1 << 26;
+    const TOGGLE_SPLINTER_KNOCKBACK: u32 = 1 << 27;
+
+    fn read(ctx: &Context, controls: &Controls) -> Self {
+        let held = controls.held().into_iter()
+            .filter(|&(key, _)| ctx.keyboard.is_key_pressed(key));
+        let pressed = controls.pressed().into_iter()
+            .filter(|&(key, _)| ctx.keyboard.is_key_just_pressed(key));
+        Input(held.chain(pressed).fold(0, |bits, (_, flag)| bits | flag))
+    }
+    fn pressed_mask() -> u32 {
+        Controls::DEFAULT.pressed().iter().fold(0, |bits, &(_, flag)| bits | flag)
+    }
+    const fn has(self, flag: u32) -> bool {
+        self.0 & flag != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct HighScores([[u32; 2]; 3]);
+
+impl HighScores {
+    fn get(&self, difficulty: Difficulty, panic_slow: bool) -> u32 {
+        self.0[difficulty as usize][panic_slow as usize]
+    }
+    fn set(&mut self, difficulty: Difficulty, panic_slow: bool, score: u32) {
+        self.0[difficulty as usize][panic_slow as usize] = score;
+    }
+    fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+        for difficulty in Difficulty::ALL {
+            for panic_slow in [false, true] {
+                let tag = if panic_slow { "panic" } else { "normal" };
+                writeln!(out, "{} {tag} {}", difficulty.name(), self.get(difficulty, panic_slow))?;
+            }
+        }
+        Ok(())
+    }
+    fn parse(text: &str) -> Self {
+        let mut scores = HighScores::default();
+        if let Ok(score) = text.trim().parse() {
+            scores.set(Difficulty::Normal, false, score);
+            return scores;
+        }
+        for line in text.lines() {
+            let mut words = line.split_whitespace();
+            let entry = (|| {
+                let difficulty = words.next()?.parse().ok()?;
+                let panic_slow = match words.next()? {
+                    "normal" => false,
+                    "panic" => true,
+                    _ => return None,
+                };
+                Some((difficulty, panic_slow, words.next()?.parse().ok()?))
+            })();
+            match entry {
+                Some((difficulty, panic_slow, score)) => scores.set(difficulty, panic_slow, score),
+                None => eprintln!("ignoring malformed high score line {line:?}"),
+            }
+        }
+        scores
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Replay {
+    seed: u64,
+    difficulty: Difficulty,
+    toggles: u32,
+    config: Config,
+    inputs: Vec<Input>,
+}
+
+impl Replay {
+    const fn new(seed: u64, difficulty: Difficulty, toggles: u32, config: Config) -> Self {
+        Replay { seed, difficulty, toggles, config, inputs: Vec::new() }
+    }
+    fn tuned(&self, config: Config) -> Config {
+        Config {
+            rot_speed: self.config.rot_speed,
+            acceleration: self.config.acceleration,
+            crate_spawn_rate: self.config.crate_spawn_rate,
+            bullet_speed: self.config.bullet_speed,
+            max_ship_speed: self.config.max_ship_speed,
+            crate_limit: self.config.crate_limit,
+            spawn_distribution: self.config.spawn_distribution,
+            density: self.config.density,
+            ..config
+        }
+    }
+    fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+        writeln!(out, "shooty-replay {REPLAY_VERSION} {} {} {:x}", self.seed, self.difficulty.name(), self.toggles)?;
+        let c = &self.config;
+        writeln!(out, "{} {} {} {} {} {} {:?} {}", c.rot_speed, c.acceleration, c.crate_spawn_rate, c.bullet_speed, c.max_ship_speed, c.crate_limit, c.spawn_distribution, c.density)?;
+        for input in &self.inputs {
+            writeln!(out, "{:x}", input.0)?;
         }
+        Ok(())
+    }
+    fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        let mut header = lines.next()?.split_whitespace();
+        if header.next()? != "shooty-replay" || header.next()?.parse::<u32>().ok()? != REPLAY_VERSION {
+            return None;
+        }
+        let seed = header.next()?.parse().ok()?;
+        let difficulty = header.next()?.parse().ok()?;
+        let toggles = u32::from_str_radix(header.next()?, 16).ok()?;
+        let mut tuning = lines.next()?.split_whitespace();
+        let mut value = || tuning.next()?.parse::<f32>().ok();
+        let config = Config {
+            rot_speed: value()?,
+            acceleration: value()?,
+            crate_spawn_rate: value()?,
+            bullet_speed: value()?,
+            max_ship_speed: value()?,
+            ..Config::DEFAULT
+        };
+        let config = Config { crate_limit: tuning.next()?.parse().ok()?, spawn_distribution: tuning.next()?.parse().ok()?, ..config };
+        let config = Config { density: tuning.next()?.parse().ok()?, ..config };
+        let inputs = lines.map(|line| u32::from_str_radix(line, 16).ok().map(Input)).collect::<Option<_>>()?;
+        Some(Replay { seed, difficulty, toggles, config, inputs })
     }
+    fn load(ctx: &Context) -> GameResult<Self> {
+        let mut text = String::new();
+        ctx.fs.open(REPLAY_PATH)?.read_to_string(&mut text)?;
+        Replay::parse(&text).ok_or_else(|| GameError::CustomError(format!("{REPLAY_PATH} is not a valid replay")))
+    }
+}
 
-    let state = MainState::new(&ctx)?;
-    event::run(ctx, event_loop, state)
+#[derive(Serialize, Deserialize)]
+struct Snapshot<'a> {
+    version: u32,
+    seed: u64,
+    step: u64,
+    play_time: f32,
+    score: u32,
+    combo: Combo,
+    hp: f32,
+    invuln: f32,
+    bombs: u32,
+    run_difficulty: Difficulty,
+    panic_run: bool,
+    wave: u32,
+    wave_spawned: u32,
+    wave_break: f32,
+    difficulty: Difficulty,
+    weapon_mode: WeaponMode,
+    crate_spawn_time: f32,
+    enemy_spawn_time: f32,
+    magnet_spawn_time: f32,
+    fire_cooldown: f32,
+    missile_cooldown: f32,
+    rapid_fire: f32,
+    triple_shot: f32,
+    shield: f32,
+    slow_mo: f32,
+    bullet_time: f32,
+    bullet_time_cooldown: f32,
+    dash: f32,
+    dash_cooldown: f32,
+    ship: Cow<'a, Obj>,
+    bullets: Cow<'a, [Bullet]>,
+    missiles: Cow<'a, [Missile]>,
+    enemy_bullets: Cow<'a, [Bullet]>,
+    crates: Cow<'a, [Obj]>,
+    enemies: Cow<'a, [Enemy]>,
+    boss: Option<Boss>,
+    splinters: Cow<'a, [Bullet]>,
+    magnets: Cow<'a, [Magnet]>,
+    power_ups: Cow<'a, [PowerUp]>,
+}
+
+impl Snapshot<'static> {
+    fn initial(seed: u64, config: &Config, difficulty: Difficulty) -> Self {
+        Snapshot {
+            version: SAVE_VERSION,
+            seed,
+            step: 0,
+            play_time: 0.,
+            score: 0,
+            combo: Combo::default(),
+            hp: SHIP_HP,
+            invuln: 0.,
+            bombs: BOMB_CHARGES,
+            run_difficulty: difficulty,
+            panic_run: false,
+            wave: 1,
+            wave_spawned: 0,
+            wave_break: 0.,
+            difficulty,
+            weapon_mode: WeaponMode::Single,
+            crate_spawn_time: -config.scaled_spawn_rate() * INITIAL_CRATES as f32,
+            enemy_spawn_time: ENEMY_SPAWN_RATE,
+            magnet_spawn_time: MAGNET_SPAWN_RATE,
+            fire_cooldown: 0.,
+            missile_cooldown: 0.,
+            rapid_fire: 0.,
+            triple_shot: 0.,
+            shield: 0.,
+            slow_mo: 1.,
+            bullet_time: 0.,
+            bullet_time_cooldown: 0.,
+            dash: 0.,
+            dash_cooldown: 0.,
+            ship: Cow::Owned(Obj { mass: SHIP_MASS, ..Obj::new(0.5 * WIDTH, 0.5 * HEIGHT) }),
+            bullets: Cow::Borrowed(&[]),
+            missiles: Cow::Borrowed(&[]),
+            enemy_bullets: Cow::Borrowed(&[]),
+            crates: Cow::Borrowed(&[]),
+            enemies: Cow::Borrowed(&[]),
+            boss: None,
+            splinters: Cow::Borrowed(&[]),
+            magnets: Cow::Borrowed(&[]),
+            power_ups: Cow::Borrowed(&[]),
+        }
+    }
+}
+
+struct MainState {
+    ship: Obj,
+    bullets: Vec<Bullet>,
+    missiles: Vec<Missile>,
+    enemy_bullets: Vec<Bullet>,
+    crates: Vec<Obj>,
+    enemies: Vec<Enemy>,
+    boss: Option<Boss>,
+    splinters: Vec<Bullet>,
+    streaks: Vec<Bullet>,
+    exhaust: Vec<Bullet>,
+    magnets: Vec<Magnet>,
+    power_ups: Vec<PowerUp>,
+    input_log: VecDeque<(u64, Input, u64)>,
+    recording: Option<Replay>,
+    playback: Option<Replay>,
+    stars: Vec<(Vec2, f32)>,
+    travel: Vec2,
+    seed: u64,
+    rng: StdRng,
+    shake_rng: StdRng,
+    sounds: Vec<Sound>,
+    queued_input: Input,
+    stick: Vec2,
+
+    ship_img: Image,
+    crate_batch: InstanceArray,
+    bullet_img: Image,
+    bullet_batch: InstanceArray,
+    missile_img: Image,
+    splinter_img: Image,
+    splinter_batch: InstanceArray,
+    power_up_img: Image,
+    scene_img: ScreenImage,
+    crt_shader: Option<Shader>,
+    glow: ScreenImage,
+    bloom_shader: Option<Shader>,
+    shoot_sound: Option<Source>,
+    break_sound: Option<Source>,
+
+    crate_spawn_time: f32,
+    enemy_spawn_time: f32,
+    wave: u32,
+    wave_spawned: u32,
+    wave_break: f32,
+    magnet_spawn_time: f32,
+    play_time: f32,
+    accumulator: f32,
+    step: u64,
+    fire_cooldown: f32,
+    missile_cooldown: f32,
+    rapid_fire: f32,
+    triple_shot: f32,
+    shield: f32,
+    exhaust_time: f32,
+    muzzle_flash: f32,
+    slow_mo: f32,
+    bullet_time: f32,
+    bullet_time_cooldown: f32,
+    dash: f32,
+    dash_cooldown: f32,
+    difficulty: Difficulty,
+    weapon_mode: WeaponMode,
+    thrust_response: ThrustResponse,
+    score: u32,
+    combo: Combo,
+    high_scores: HighScores,
+    hp: f32,
+    invuln: f32,
+    bombs: u32,
+    run_difficulty: Difficulty,
+    panic_run: bool,
+    bomb_flash: f32,
+    selected: Option<Selection>,
+    hud_layout: HudLayout,
+    controls: Controls,
+    config: Config,
+    args: Args,
+    camera: Vec2,
+    zoom: f32,
+    shake: f32,
+    window: Vec2,
+    screenshot_requested: bool,
+    palette: Palette,
+
+    debug: bool,
+    diagnostics: bool,
+    photo_mode: bool,
+    fullscreen: bool,
+    paused: bool,
+    focus_paused: bool,
+    auto_resume: bool,
+    scene: Scene,
+    settings_row: usize,
+    bounce_edge: bool,
+    show_vel: bool,
+    collect_splinters: bool,
+    splinter_knockback: bool,
+    magnetic_edge: bool,
+    auto_brake: bool,
+    mouse_aim: bool,
+    follow_camera: bool,
+    panic_slow: bool,
+    sub_stepping: bool,
+    spawn_magnets: bool,
+    spawning_enabled: bool,
+    fog: bool,
+    show_trajectories: bool,
+    aim_assist: bool,
+    crt: bool,
+    bloom: bool,
+}
+
+impl MainState {
+    fn new(ctx: &Context, config: Config, args: Args) -> GameResult<MainState> {
+        let playback = if args.replay {
+            match Replay::load(ctx) {
+                Ok(replay) => Some(replay),
+                Err(e) => {
+                    eprintln!("could not load replay: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let config = playback.as_ref().map_or(config, |r| r.tuned(config));
+        let seed = playback.as_ref().map(|r| r.seed).or(args.seed).unwrap_or_else(seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let stars = starfield(&mut rng);
+        let bullet_img = load_image(ctx, "/bullet.png")?;
+        let splinter_img = load_image(ctx, "/splinter.png")?;
+        let mut s = MainState {
+            crate_spawn_time: -config.scaled_spawn_rate() * INITIAL_CRATES as f32,
+            enemy_spawn_time: ENEMY_SPAWN_RATE,
+            wave: 1,
+            wave_spawned: 0,
+            wave_break: 0.,
+            magnet_spawn_time: MAGNET_SPAWN_RATE,
+            play_time: 0.,
+            accumulator: 0.,
+            step: 0,
+            fire_cooldown: 0.,
+            missile_cooldown: 0.,
+            rapid_fire: 0.,
+            triple_shot: 0.,
+            shield: 0.,
+            exhaust_time: 0.,
+            muzzle_flash: 0.,
+            slow_mo: 1.,
+            bullet_time: 0.,
+            bullet_time_cooldown: 0.,
+            dash: 0.,
+            dash_cooldown: 0.,
+            difficulty: playback.as_ref().map_or(config.difficulty, |r| r.difficulty),
+            weapon_mode: WeaponMode::Single,
+            thrust_response: ThrustResponse::Linear,
+            ship: Obj { mass: SHIP_MASS, ..Obj::new(0.5 * WIDTH, 0.5 * HEIGHT) },
+            bullets: Vec::new(),
+            missiles: Vec::new(),
+            enemy_bullets: Vec::new(),
+            crates: Vec::new(),
+            enemies: Vec::new(),
+            boss: None,
+            splinters: Vec::new(),
+            streaks: Vec::new(),
+            exhaust: Vec::new(),
+            magnets: Vec::new(),
+            power_ups: Vec::new(),
+            input_log: VecDeque::with_capacity(INPUT_LOG_LEN),
+            recording: None,
+            seed,
+            rng,
+            shake_rng: StdRng::seed_from_u64(seed),
+            stars,
+            travel: Vec2::ZERO,
+            sounds: Vec::new(),
+            queued_input: Input::default(),
+            stick: Vec2::ZERO,
+            ship_img: load_image(ctx, "/ship.png")?,
+            crate_batch: InstanceArray::new(ctx, load_image(ctx, "/crate.png")?),
+            bullet_batch: InstanceArray::new(ctx, bullet_img.clone()),
+            bullet_img,
+            missile_img: load_image(ctx, "/missile.png")?,
+            splinter_batch: InstanceArray::new(ctx, splinter_img.clone()),
+            splinter_img,
+            power_up_img: load_image(ctx, "/powerup.png")?,
+            scene_img: ScreenImage::new(ctx, None, 1., 1., 1),
+            crt_shader: load_shader(ctx, "/crt.wgsl"),
+            glow: ScreenImage::new(ctx, None, 1., 1., 1),
+            bloom_shader: load_shader(ctx, "/bloom.wgsl"),
+            shoot_sound: load_sound(ctx, "/shoot.wav", config.volume),
+            break_sound: load_sound(ctx, "/break.wav", config.volume),
+            score: 0,
+            combo: Combo::default(),
+            high_scores: load_high_scores(ctx),
+            hp: SHIP_HP,
+            invuln: 0.,
+            bombs: BOMB_CHARGES,
+            run_difficulty: Difficulty::Normal,
+            panic_run: false,
+            bomb_flash: 0.,
+            selected: None,
+            hud_layout: HudLayout::DEFAULT,
+            controls: Controls::DEFAULT,
+            config,
+            args,
+            camera: Vec2::ZERO,
+            zoom: 1.,
+            shake: 0.,
+            window: Vec2::from(ctx.gfx.drawable_size()),
+            screenshot_requested: false,
+            palette: Palette::DEFAULT,
+            debug: false,
+            diagnostics: false,
+            photo_mode: false,
+            fullscreen: false,
+            focus_paused: false,
+            auto_resume: true,
+            paused: false,
+            scene: if playback.is_some() { Scene::Playing } else { Scene::Menu },
+            playback,
+            settings_row: 0,
+            bounce_edge: config.bounce,
+            show_vel: false,
+            collect_splinters: false,
+            splinter_knockback: false,
+            magnetic_edge: false,
+            auto_brake: false,
+            mouse_aim: false,
+            follow_camera: false,
+            panic_slow: false,
+            sub_stepping: false,
+            spawn_magnets: false,
+            spawning_enabled: true,
+            fog: false,
+            show_trajectories: false,
+            aim_assist: false,
+            crt: false,
+            bloom: false,
+        };
+        if let Some(toggles) = s.playback.as_ref().map(|r| r.toggles) {
+            s.set_toggles(toggles);
+            s.run_difficulty = s.difficulty;
+            s.panic_run = s.panic_slow;
+        }
+        Ok(s)
+    }
+    fn reset(&mut self) {
+        let seed = self.args.seed.unwrap_or_else(seed);
+        self.restore(Snapshot::initial(seed, &self.config, self.difficulty));
+        self.shake_rng = StdRng::seed_from_u64(self.seed);
+        self.stars = starfield(&mut self.rng);
+        self.exhaust_time = 0.;
+        self.muzzle_flash = 0.;
+        self.travel = Vec2::ZERO;
+        self.sounds.clear();
+        self.queued_input = Input::default();
+        self.stick = Vec2::ZERO;
+        self.bomb_flash = 0.;
+        self.camera = Vec2::ZERO;
+        self.shake = 0.;
+        self.paused = false;
+        self.focus_paused = false;
+        self.bounce_edge = self.config.bounce;
+        self.playback = None;
+    }
+    fn toggles(&self) -> u32 {
+        [
+            (self.bounce_edge, Input::TOGGLE_BOUNCE),
+            (self.collect_splinters, Input::TOGGLE_COLLECT),
+            (self.magnetic_edge, Input::TOGGLE_MAGNETIC_EDGE),
+            (self.auto_brake, Input::TOGGLE_AUTO_BRAKE),
+            (self.spawn_magnets, Input::TOGGLE_MAGNETS),
+            (self.panic_slow, Input::TOGGLE_PANIC_SLOW),
+            (self.sub_stepping, Input::TOGGLE_SUB_STEPPING),
+            (self.thrust_response == ThrustResponse::Snappy, Input::TOGGLE_THRUST_RESPONSE),
+            (self.spawning_enabled, Input::TOGGLE_SPAWNING),
+            (self.splinter_knockback, Input::TOGGLE_SPLINTER_KNOCKBACK),
+        ].into_iter().filter(|&(on, _)| on).fold(0, |bits, (_, flag)| bits | flag)
+    }
+    fn set_toggles(&mut self, bits: u32) {
+        let input = Input(bits);
+        self.bounce_edge = input.has(Input::TOGGLE_BOUNCE);
+        self.collect_splinters = input.has(Input::TOGGLE_COLLECT);
+        self.magnetic_edge = input.has(Input::TOGGLE_MAGNETIC_EDGE);
+        self.auto_brake = input.has(Input::TOGGLE_AUTO_BRAKE);
+        self.spawn_magnets = input.has(Input::TOGGLE_MAGNETS);
+        self.panic_slow = input.has(Input::TOGGLE_PANIC_SLOW);
+        self.sub_stepping = input.has(Input::TOGGLE_SUB_STEPPING);
+        self.thrust_response = if input.has(Input::TOGGLE_THRUST_RESPONSE) { ThrustResponse::Snappy } else { ThrustResponse::Linear };
+        self.spawning_enabled = input.has(Input::TOGGLE_SPAWNING);
+        self.splinter_knockback = input.has(Input::TOGGLE_SPLINTER_KNOCKBACK);
+    }
+    fn live_aim(&self) -> bool {
+        self.playback.is_none() && self.recording.is_none()
+    }
+    fn max_speed(&self) -> f32 {
+        iter::once(&self.ship)
+            .chain(self.bullets.iter().map(|b| &b.obj))
+            .chain(self.enemy_bullets.iter().map(|b| &b.obj))
+            .chain(&self.crates)
+            .chain(self.enemies.iter().map(|e| &e.obj))
+            .chain(self.splinters.iter().map(|b| &b.obj))
+            .map(|obj| obj.vel.length())
+            .fold(0., f32::max)
+    }
+    // Crate indices shift on swap_remove, so any removal invalidates missile targets
+    fn retarget_missiles(&mut self) {
+        for missile in &mut self.missiles {
+            missile.target = None;
+        }
+    }
+    fn physics(&mut self, dt: f32) {
+        let wrap = !self.bounce_edge;
+        for splinter in &mut self.splinters {
+            splinter.obj.vel *= SPLINTER_DRAG.powf(dt);
+            splinter.obj.rot_v *= SPLINTER_ANGULAR_DAMPING.powf(dt);
+        }
+        for crat in &mut self.crates {
+            crat.rot_v *= CRATE_ANGULAR_DAMPING.powf(dt);
+        }
+        self.ship.vel *= SHIP_DRAG.powf(dt);
+
+        for bullet in &mut self.bullets {
+            bullet.prev = bullet.obj.pos;
+        }
+        let iter = iter::once(&mut self.ship)
+            .chain(self.bullets.iter_mut().map(|b| &mut b.obj))
+            .chain(self.missiles.iter_mut().map(|m| &mut m.obj))
+            .chain(self.enemy_bullets.iter_mut().map(|b| &mut b.obj))
+            .chain(&mut self.crates)
+            .chain(self.enemies.iter_mut().map(|e| &mut e.obj))
+            .chain(self.boss.iter_mut().map(|b| &mut b.obj))
+            .chain(self.splinters.iter_mut().map(|b| &mut b.obj))
+            .chain(self.power_ups.iter_mut().map(|p| &mut p.obj));
+        for obj in iter {
+            if let Some(old_pos) = integrate(obj, dt, self.bounce_edge, self.magnetic_edge) {
+                if self.magnetic_edge {
+                    self.streaks.push(Obj::from(old_pos, obj.vel, 0.).bullet(STREAK_TTL));
+                    self.streaks.push(Obj::from(obj.pos, obj.vel, 0.).bullet(STREAK_TTL));
+                }
+            }
+        }
+
+        cancel_bullets(&mut self.bullets, &mut self.enemy_bullets, wrap);
+
+        let crates = &mut self.crates;
+        let enemies = &mut self.enemies;
+        let splinters = &mut self.splinters;
+        let score = &mut self.score;
+        let combo = &mut self.combo;
+        let sounds = &mut self.sounds;
+        let power_ups = &mut self.power_ups;
+        let rng = &mut self.rng;
+        let shake = &mut self.shake;
+        let boss = &mut self.boss;
+        let per_crate = self.config.splinters_per_crate();
+        let mut crates_removed = false;
+        self.bullets.retain(|bullet| {
+            if let Some(b) = boss.as_mut().filter(|b| swept_hit(bullet, iter::once(&b.obj), wrap).is_some()) {
+                b.hp -= 1.;
+                if b.hp <= 0. {
+                    sounds.push(Sound::Break);
+                    *shake += SHAKE_HIT;
+                    shatter(rng, splinters, per_crate, Obj { size: FULL_SIZE, ..b.obj }, bullet.obj.vel);
+                    *score += BOSS_POINTS;
+                    *boss = None;
+                }
+                return false;
+            }
+            if let Some(e) = swept_hit(bullet, enemies.iter().map(|e| &e.obj), wrap) {
+                sounds.push(Sound::Break);
+                let enemy = enemies.swap_remove(e);
+                shatter(rng, splinters, per_crate, enemy.obj, bullet.obj.vel);
+                *score += ENEMY_POINTS;
+                return false;
+            }
+            let Some(c) = swept_hit(bullet, crates.iter(), wrap) else {
+                return true;
+            };
+            if bullet.kind == BulletKind::Shockwave {
+                sounds.push(Sound::Break);
+                *shake += SHAKE_CRATE;
+                let centre = bullet.obj.pos;
+                let delta = |c: &Obj| if wrap { wrapped_delta(c.pos, centre) } else { c.pos - centre };
+                let mut caught: Vec<_> = Grid::new(crates.iter(), wrap)
+                    .within(centre, BLAST_RADIUS)
+                    .filter(|&c| delta(&crates[c]).length_squared() < BLAST_RADIUS * BLAST_RADIUS)
+                    .chain(iter::once(c))
+                    .collect();
+                caught.sort_unstable_by(|a, b| b.cmp(a));
+                caught.dedup();
+                for c in caught {
+                    let push = delta(&crates[c]).normalize_or_zero() * SHOCKWAVE_PUSH;
+                    *score += combo.kill(destroy_crate_fully(rng, crates, splinters, per_crate, power_ups, c, push, wrap));
+                }
+                crates_removed = true;
+                return false;
+            }
+            if !crates[c].hit(bullet.obj.vel) {
+                sounds.push(Sound::Break);
+                *shake += SHAKE_CRATE;
+                *score += combo.kill(destroy_crate(rng, crates, splinters, per_crate, power_ups, c, bullet.obj.vel, wrap));
+                crates_removed = true;
+            }
+            false
+        });
+        self.missiles.retain(|missile| {
+            let Some(c) = bullet_hit(&missile.obj, crates.iter(), wrap) else {
+                return true;
+            };
+            if !crates[c].hit(missile.obj.vel) {
+                sounds.push(Sound::Break);
+                *shake += SHAKE_CRATE;
+                *score += combo.kill(destroy_crate(rng, crates, splinters, per_crate, power_ups, c, missile.obj.vel, wrap));
+                crates_removed = true;
+            }
+            false
+        });
+        if crates_removed {
+            self.retarget_missiles();
+        }
+
+        let ship = &self.ship;
+        let mut damage = 0.;
+        self.enemy_bullets.retain(|bullet| {
+            if bullet_hit(&bullet.obj, iter::once(ship), wrap).is_some() {
+                damage += ENEMY_BULLET_DAMAGE;
+                false
+            } else {
+                true
+            }
+        });
+        self.hurt(damage);
+
+        if self.collect_splinters {
+            let ship_pos = self.ship.pos;
+            let before = self.splinters.len();
+            self.splinters.retain(|s| (s.obj.pos - ship_pos).length_squared() >= SPLINTER_COLLECT_DIST * SPLINTER_COLLECT_DIST);
+            self.score += (before - self.splinters.len()) as u32 * SPLINTER_POINTS;
+        }
+        if self.splinter_knockback {
+            let reach = self.ship.radius + SPLINTER_HIT_RADIUS;
+            for splinter in &mut self.splinters {
+                let d = if wrap { wrapped_delta(self.ship.pos, splinter.obj.pos) } else { self.ship.pos - splinter.obj.pos };
+                if d.length_squared() >= reach * reach {
+                    continue;
+                }
+                let n = d.normalize_or_zero();
+                let closing = (splinter.obj.vel - self.ship.vel).dot(n);
+                if closing > 0. {
+                    self.ship.vel += SPLINTER_KNOCKBACK * closing * n;
+                    splinter.obj.vel -= 2. * closing * n;
+                }
+            }
+        }
+
+        let ship = &self.ship;
+        let mut collected = Vec::new();
+        self.power_ups.retain(|power_up| {
+            let d = if wrap { wrapped_delta(ship.pos, power_up.obj.pos) } else { ship.pos - power_up.obj.pos };
+            let reach = ship.radius + POWER_UP_RADIUS;
+            let near = d.length_squared() < reach * reach;
+            if near {
+                collected.push(power_up.kind);
+            }
+            !near
+        });
+        for kind in collected {
+            match kind {
+                PowerKind::RapidFire => self.rapid_fire = RAPID_FIRE_TIME,
+                PowerKind::TripleShot => self.triple_shot = TRIPLE_SHOT_TIME,
+                PowerKind::Shield => self.shield = SHIELD_TIME,
+            }
+        }
+
+        resolve_crates(&mut self.crates, wrap);
+        let hazards = self.crates.iter_mut()
+            .chain(self.enemies.iter_mut().map(|e| &mut e.obj))
+            .chain(self.boss.iter_mut().map(|b| &mut b.obj));
+        let mut damage = 0.;
+        for obj in hazards {
+            if let Some(impact) = self.ship.resolve(obj, wrap) {
+                damage += impact * CRASH_DAMAGE;
+            }
+        }
+        self.hurt(damage);
+        if self.hp <= 0. {
+            self.hp = 0.;
+            self.scene = Scene::GameOver;
+        }
+    }
+    fn fire_interval(&self) -> f32 {
+        if self.rapid_fire > 0. { FIRE_COOLDOWN * RAPID_FIRE_FACTOR } else { FIRE_COOLDOWN }
+    }
+    fn fire(&mut self) {
+        self.fire_cooldown = self.fire_interval();
+        let mode = match self.weapon_mode {
+            WeaponMode::Single if self.triple_shot > 0. => WeaponMode::Spread,
+            mode => mode,
+        };
+        match mode {
+            WeaponMode::Single => self.bullets.push(shoot(&mut self.rng, &self.ship, self.ship.rot, self.config.bullet_speed)),
+            WeaponMode::Spread => {
+                for spread in [-TRIPLE_SHOT_SPREAD, 0., TRIPLE_SHOT_SPREAD] {
+                    self.bullets.push(shoot(&mut self.rng, &self.ship, self.ship.rot + spread, self.config.bullet_speed));
+                }
+            }
+            WeaponMode::Shotgun => {
+                for n in 0..SHOTGUN_PELLETS {
+                    let spread = SHOTGUN_SPREAD * (2. * n as f32 / (SHOTGUN_PELLETS - 1) as f32 - 1.);
+                    let mut pellet = shoot(&mut self.rng, &self.ship, self.ship.rot + spread, self.config.bullet_speed);
+                    pellet.ttl *= SHOTGUN_TTL_FACTOR;
+                    self.bullets.push(pellet);
+                }
+            }
+        }
+        self.sounds.push(Sound::Shoot);
+        self.muzzle_flash = MUZZLE_FLASH_TIME;
+        self.ship.vel -= angle_to_vec(self.ship.rot) * BulletKind::Normal.recoil();
+    }
+    fn hurt(&mut self, damage: f32) {
+        if damage > 0. && self.invuln <= 0. && self.shield <= 0. {
+            self.hp -= damage;
+            self.invuln = INVULN_TIME;
+            self.shake += SHAKE_HIT;
+        }
+    }
+    fn step(&mut self, input: Input) {
+        let dt = DELTA * self.time_scale();
+        // Peeking a draw from a clone fingerprints the RNG state without advancing it
+        let rng_state: u64 = self.rng.clone().random();
+
+        let wave_spawning = self.wave_spawned < wave_crates(self.wave) && self.wave_break <= 0. && self.boss.is_none();
+        if self.crate_spawn_time <= 0. && self.play_time >= CRATE_GRACE_PERIOD && wave_spawning && self.spawn_crate() {
+            self.crate_spawn_time += self.wave_spawn_rate();
+            self.wave_spawned += 1;
+        }
+        if self.enemy_spawn_time <= 0. && self.wave >= ENEMY_FIRST_WAVE {
+            let pos = self.config.spawn_distribution.sample(&mut self.rng, self.ship.pos);
+            let safe_radius = spawn_safe_radius(self.play_time);
+            if (self.ship.pos - pos).length_squared() >= safe_radius * safe_radius {
+                self.enemy_spawn_time += ENEMY_SPAWN_RATE;
+                let obj = Obj { mass: ENEMY_MASS, ..Obj::from(pos, Vec2::ZERO, 0.) };
+                self.enemies.push(Enemy { obj, fire_cooldown: ENEMY_FIRE_INTERVAL });
+            }
+        }
+
+        if self.input_log.len() == INPUT_LOG_LEN {
+            self.input_log.pop_front();
+        }
+        self.input_log.push_back((self.step, input, rng_state));
+        if let Some(recording) = &mut self.recording {
+            recording.inputs.push(input);
+        }
+        self.step += 1;
+
+        self.play_time += dt;
+        if self.spawning_enabled && self.crates.len() < self.crate_limit() && self.play_time >= CRATE_GRACE_PERIOD && wave_spawning {
+            self.crate_spawn_time -= dt;
+        }
+        if self.enemies.len() < ENEMY_LIMIT && self.play_time >= CRATE_GRACE_PERIOD && self.wave >= ENEMY_FIRST_WAVE {
+            self.enemy_spawn_time -= dt;
+        }
+        self.wave_break = (self.wave_break - dt).max(0.);
+        if self.remaining_crates() == 0 && self.boss.is_none() {
+            self.wave += 1;
+            self.wave_spawned = 0;
+            self.wave_break = WAVE_BREAK;
+            if self.spawning_enabled {
+                self.crate_spawn_time = 0.;
+            }
+            if self.wave.is_multiple_of(BOSS_WAVE_INTERVAL) {
+                let far = self.ship.pos + 0.5 * Vec2::new(WIDTH, HEIGHT);
+                self.boss = Some(Boss::new(Vec2::new(far.x.rem_euclid(WIDTH), far.y.rem_euclid(HEIGHT))));
+            }
+        }
+        let wrap = !self.bounce_edge;
+        for enemy in &mut self.enemies {
+            enemy.steer(self.ship.pos, wrap, dt);
+            enemy.fire_cooldown -= dt;
+            if enemy.fire_cooldown <= 0. {
+                enemy.fire_cooldown = ENEMY_FIRE_INTERVAL;
+                let d = if wrap { wrapped_delta(self.ship.pos, enemy.obj.pos) } else { self.ship.pos - enemy.obj.pos };
+                let dir = d.normalize_or_zero();
+                let obj = Obj::from(enemy.obj.pos + dir * enemy.obj.radius, enemy.obj.vel + dir * ENEMY_BULLET_SPEED, dir.y.atan2(dir.x));
+                self.enemy_bullets.push(obj.bullet(ENEMY_BULLET_TTL));
+            }
+        }
+
+        for missile in &mut self.missiles {
+            missile.steer(&self.crates, wrap, dt);
+            let trail = -angle_to_vec(missile.obj.rot);
+            let spread = trail.perp() * self.rng.random_range(-EXHAUST_SPREAD .. EXHAUST_SPREAD);
+            self.exhaust.push(Obj::from(missile.obj.pos + trail * MISSILE_LENGTH, trail * EXHAUST_SPEED + spread, 0.).bullet(MISSILE_TRAIL_TTL));
+        }
+        self.missiles.retain(|missile| missile.ttl > 0.);
+
+        if let Some(boss) = &mut self.boss {
+            let d = if wrap { wrapped_delta(self.ship.pos, boss.obj.pos) } else { self.ship.pos - boss.obj.pos };
+            boss.obj.vel = d.normalize_or_zero() * BOSS_SPEED;
+            boss.obj.rot = d.y.atan2(d.x);
+            boss.fire_cooldown -= dt;
+            if boss.fire_cooldown <= 0. {
+                boss.fire_cooldown = BOSS_FIRE_INTERVAL;
+                let phase = self.rng.random_range(0. .. TAU);
+                for n in 0..BOSS_RING_SHOTS {
+                    let angle = phase + n as f32 * TAU / BOSS_RING_SHOTS as f32;
+                    let dir = angle_to_vec(angle);
+                    let obj = Obj::from(boss.obj.pos + dir * boss.obj.radius, boss.obj.vel + dir * ENEMY_BULLET_SPEED, angle);
+                    self.enemy_bullets.push(obj.bullet(ENEMY_BULLET_TTL));
+                }
+            }
+        }
+
+        expire(&mut self.rng, &mut self.bullets, dt);
+        expire(&mut self.rng, &mut self.enemy_bullets, dt);
+        expire(&mut self.rng, &mut self.splinters, dt);
+        expire(&mut self.rng, &mut self.streaks, dt);
+        expire(&mut self.rng, &mut self.exhaust, dt);
+        for particle in &mut self.exhaust {
+            particle.obj.pos += particle.obj.vel * dt;
+        }
+
+        if self.spawn_magnets {
+            self.magnet_spawn_time -= dt;
+            if self.magnet_spawn_time <= 0. {
+                self.magnet_spawn_time += MAGNET_SPAWN_RATE;
+                let pos = Vec2::new(self.rng.random_range(0. .. WIDTH), self.rng.random_range(0. .. HEIGHT));
+                self.magnets.push(Magnet { pos, ttl: MAGNET_TTL });
+            }
+        }
+        let crates = &mut self.crates;
+        self.magnets.retain_mut(|magnet| {
+            magnet.ttl -= dt;
+            for crat in crates.iter_mut() {
+                let d = magnet.pos - crat.pos;
+                let dist_sq = d.length_squared();
+                if dist_sq < MAGNET_RADIUS * MAGNET_RADIUS {
+                    if magnet.ttl > 0. {
+                        crat.vel += MAGNET_STRENGTH / dist_sq.max(32. * 32.) * d.normalize_or_zero() * dt;
+                    } else {
+                        crat.vel -= MAGNET_DISPERSE_SPEED * d.normalize_or_zero();
+                    }
+                }
+            }
+            magnet.ttl > 0.
+        });
+
+        self.fire_cooldown = (self.fire_cooldown - dt).max(0.);
+        self.muzzle_flash = (self.muzzle_flash - dt).max(0.);
+        for crat in &mut self.crates {
+            crat.flash = (crat.flash - dt).max(0.);
+            crat.age += dt;
+        }
+        self.combo.tick(dt);
+        self.invuln = (self.invuln - DELTA).max(0.);
+        self.shake = (self.shake.min(1.) - SHAKE_DECAY * DELTA).max(0.);
+        self.rapid_fire = (self.rapid_fire - dt).max(0.);
+        self.triple_shot = (self.triple_shot - dt).max(0.);
+        self.shield = (self.shield - dt).max(0.);
+        self.power_ups.retain_mut(|power_up| {
+            power_up.ttl -= dt;
+            power_up.ttl > 0.
+        });
+        let wants_fire = (input.has(Input::FIRE) || input.has(Input::FIRE_HELD)) && self.fire_cooldown <= 0.;
+        if wants_fire {
+            self.fire();
+        }
+        if input.has(Input::FIRE_CLUSTER) && self.fire_cooldown <= 0. {
+            self.fire_cooldown = CLUSTER_COOLDOWN;
+            let dir = angle_to_vec(self.ship.rot);
+            let obj = Obj::from(self.ship.pos + dir * 20., self.ship.vel + dir * self.config.bullet_speed, self.ship.rot);
+            self.bullets.push(obj.cluster(self.rng.random_range(0.9 .. 1.3)));
+            self.sounds.push(Sound::Shoot);
+            self.muzzle_flash = MUZZLE_FLASH_TIME;
+            self.ship.vel -= dir * BulletKind::Cluster.recoil();
+        }
+        self.missile_cooldown = (self.missile_cooldown - dt).max(0.);
+        if input.has(Input::FIRE_MISSILE) && self.missile_cooldown <= 0. && self.missiles.len() < MISSILE_LIMIT {
+            self.missile_cooldown = MISSILE_COOLDOWN;
+            let dir = angle_to_vec(self.ship.rot);
+            let obj = Obj::from(self.ship.pos + dir * 20., self.ship.vel + dir * MISSILE_SPEED, self.ship.rot);
+            self.missiles.push(Missile { obj, ttl: MISSILE_TTL, target: None });
+            self.sounds.push(Sound::Shoot);
+        }
+        if input.has(Input::FIRE_SHOCKWAVE) && self.fire_cooldown <= 0. {
+            self.fire_cooldown = SHOCKWAVE_COOLDOWN;
+            let mut bullet = shoot(&mut self.rng, &self.ship, self.ship.rot, self.config.bullet_speed);
+            bullet.kind = BulletKind::Shockwave;
+            self.bullets.push(bullet);
+            self.sounds.push(Sound::Shoot);
+            self.muzzle_flash = MUZZLE_FLASH_TIME;
+            self.ship.vel -= angle_to_vec(self.ship.rot) * BulletKind::Shockwave.recoil();
+        }
+        self.bomb_flash = (self.bomb_flash - DELTA).max(0.);
+        if input.has(Input::BOMB) && self.bombs > 0 {
+            self.bombs -= 1;
+            self.bomb_flash = BOMB_FLASH_TIME;
+            self.shake += SHAKE_HIT;
+            self.sounds.push(Sound::Break);
+            // The play field rather than the camera view, so replays don't depend on zoom or panning
+            let field = Rect::new(0., 0., WIDTH, HEIGHT);
+            while let Some(c) = self.crates.iter().position(|c| field.contains(c.pos)) {
+                let push = (self.crates[c].pos - self.ship.pos).normalize_or_zero() * BOMB_PUSH;
+                self.score += self.combo.kill(destroy_crate(&mut self.rng, &mut self.crates, &mut self.splinters, self.config.splinters_per_crate(), &mut self.power_ups, c, push, !self.bounce_edge));
+            }
+            self.retarget_missiles();
+        }
+        if input.has(Input::SPAWN_CRATE) {
+            self.spawn_crate();
+        }
+        if input.has(Input::CYCLE_WEAPON) {
+            self.weapon_mode = self.weapon_mode.next();
+        }
+        if input.has(Input::CYCLE_DIFFICULTY) {
+            self.difficulty = self.difficulty.next();
+        }
+        if input.has(Input::TOGGLE_THRUST_RESPONSE) {
+            self.thrust_response = match self.thrust_response {
+                ThrustResponse::Linear => ThrustResponse::Snappy,
+                ThrustResponse::Snappy => ThrustResponse::Linear,
+            };
+        }
+        if input.has(Input::TOGGLE_BOUNCE) {
+            self.bounce_edge = !self.bounce_edge;
+        }
+        if input.has(Input::TOGGLE_COLLECT) {
+            self.collect_splinters = !self.collect_splinters;
+        }
+        if input.has(Input::TOGGLE_SPLINTER_KNOCKBACK) {
+            self.splinter_knockback = !self.splinter_knockback;
+        }
+        if input.has(Input::TOGGLE_MAGNETIC_EDGE) {
+            self.magnetic_edge = !self.magnetic_edge;
+        }
+        if input.has(Input::TOGGLE_SUB_STEPPING) {
+            self.sub_stepping = !self.sub_stepping;
+        }
+        if input.has(Input::TOGGLE_PANIC_SLOW) {
+            self.panic_slow = !self.panic_slow;
+        }
+        self.run_difficulty = self.run_difficulty.min(self.difficulty);
+        self.panic_run |= self.panic_slow;
+        self.bullet_time = (self.bullet_time - DELTA).max(0.);
+        self.bullet_time_cooldown = (self.bullet_time_cooldown - DELTA).max(0.);
+        if input.has(Input::BULLET_TIME) && self.bullet_time_cooldown <= 0. {
+            self.bullet_time = BULLET_TIME_DURATION;
+            self.bullet_time_cooldown = BULLET_TIME_COOLDOWN;
+        }
+        let target = if self.bullet_time > 0. { BULLET_TIME_SCALE } else { 1. };
+        self.slow_mo += (target - self.slow_mo) * (1. - (-BULLET_TIME_RAMP * DELTA).exp());
+        self.dash = (self.dash - dt).max(0.);
+        self.dash_cooldown = (self.dash_cooldown - dt).max(0.);
+        if input.has(Input::DASH) && self.dash_cooldown <= 0. {
+            self.dash = DASH_TIME;
+            self.dash_cooldown = DASH_COOLDOWN;
+            self.invuln = self.invuln.max(DASH_INVULN_TIME);
+            self.ship.vel += angle_to_vec(self.ship.rot) * DASH_IMPULSE;
+        }
+        if input.has(Input::TOGGLE_AUTO_BRAKE) {
+            self.auto_brake = !self.auto_brake;
+        }
+        if input.has(Input::TOGGLE_SPAWNING) {
+            self.spawning_enabled = !self.spawning_enabled;
+        }
+        if input.has(Input::TOGGLE_MAGNETS) {
+            self.spawn_magnets = !self.spawn_magnets;
+        }
+
+        let control_dt = dt / self.slow_mo;
+        if input.has(Input::ROTATE_LEFT) {
+            self.ship.rot -= self.config.rot_speed * control_dt;
+        }
+        if input.has(Input::ROTATE_RIGHT) {
+            self.ship.rot += self.config.rot_speed * control_dt;
+        }
+        
+        let mut wish_dir = Vec2::ZERO;
+        if input.has(Input::FORWARD) {
+            wish_dir.x += 1.;
+        }
+        if input.has(Input::BACKWARD) {
+            wish_dir.x -= 1.;
+        }
+        if input.has(Input::STRAFE_RIGHT) {
+            wish_dir.y += 1.;
+        }
+        if input.has(Input::STRAFE_LEFT) {
+            wish_dir.y -= 1.;
+        }
+        wish_dir += self.stick;
+        let wish_dir = wish_dir.normalize_or_zero();
+        let dir = angle_to_vec(self.ship.rot);
+
+        if input.has(Input::BRAKE) {
+            let velocity_to_cancel = self.ship.vel - self.ship.vel.dot(dir).max(0.) * dir;
+            self.ship.vel -= velocity_to_cancel.normalize_or_zero() * self.config.acceleration * control_dt;
+        } else if self.auto_brake && wish_dir == Vec2::ZERO {
+            let speed = self.ship.vel.length();
+            self.ship.vel -= self.ship.vel.normalize_or_zero() * (AUTO_BRAKE_DECEL * control_dt).min(speed);
+        }
+
+        self.exhaust_time -= dt;
+        if self.dash > 0. {
+            let spread = dir.perp() * self.rng.random_range(-EXHAUST_SPREAD .. EXHAUST_SPREAD);
+            self.exhaust.push(Obj::from(self.ship.pos - dir * self.ship.radius, spread, 0.).bullet(DASH_TRAIL_TTL));
+        }
+        if wish_dir != Vec2::ZERO {
+            if self.exhaust_time <= 0. {
+                self.exhaust_time = EXHAUST_INTERVAL;
+                let thrust = dir.rotate(wish_dir);
+                let spread = thrust.perp() * self.rng.random_range(-EXHAUST_SPREAD .. EXHAUST_SPREAD);
+                let vel = self.ship.vel - thrust * EXHAUST_SPEED + spread;
+                self.exhaust.push(Obj::from(self.ship.pos - thrust * self.ship.radius, vel, 0.).bullet(EXHAUST_TTL));
+            }
+            match self.thrust_response {
+                ThrustResponse::Linear => {
+                    let accel = dir.rotate(wish_dir) * self.config.acceleration;
+                    self.ship.vel += accel * control_dt;
+                }
+                ThrustResponse::Snappy if self.dash > 0. => (),
+                ThrustResponse::Snappy => {
+                    let target = dir.rotate(wish_dir) * SNAPPY_TOP_SPEED;
+                    self.ship.vel += (target - self.ship.vel) * (1. - (-SNAPPY_RESPONSE * control_dt).exp());
+                }
+            }
+        }
+        let max_speed = if self.dash > 0. { self.config.max_ship_speed + DASH_IMPULSE } else { self.config.max_ship_speed };
+        self.ship.vel = self.ship.vel.clamp_length_max(max_speed);
+
+        for bullet in self.bullets.iter_mut().chain(&mut self.enemy_bullets) {
+            bullet.trail.push(bullet.obj.pos);
+        }
+        let max_speed = self.max_speed();
+        for_substeps(dt, max_speed, self.sub_stepping, |dt| self.physics(dt));
+        self.travel += self.ship.vel * dt;
+    }
+    fn spawn_crate(&mut self) -> bool {
+        let safe_radius = spawn_safe_radius(self.play_time);
+        let wrap = !self.bounce_edge;
+        let spot = (0..SPAWN_ATTEMPTS)
+            .map(|_| self.config.spawn_distribution.sample(&mut self.rng, self.ship.pos))
+            .find(|&pos| {
+                (self.ship.pos - pos).length_squared() >= safe_radius * safe_radius
+                    && self.crates.iter().all(|c| {
+                        let d = if wrap { wrapped_delta(c.pos, pos) } else { c.pos - pos };
+                        let reach = c.radius + OBJ_RADIUS;
+                        d.length_squared() >= reach * reach
+                    })
+            });
+        let Some(Vec2 { x, y }) = spot else {
+            return false;
+        };
+        let speed = self.difficulty.settings().crate_speed;
+        let obj = Obj::with(
+            x, y,
+            self.rng.random_range(-speed .. speed),
+            self.rng.random_range(-speed .. speed),
+            self.rng.random_range(0. .. TAU),
+            self.rng.random_range(CRATE_ROT_V_MIN .. CRATE_ROT_V_MAX),
+        );
+        let hp = if self.rng.random_bool(tough_crate_chance(self.wave)) { TOUGH_CRATE_HP } else { 1 };
+        self.crates.push(Obj { hp, ..obj });
+        true
+    }
+    fn wave_spawn_rate(&self) -> f32 {
+        self.config.scaled_spawn_rate() * self.difficulty.settings().spawn_rate_factor * WAVE_RATE_FACTOR.powi(self.wave as i32 - 1)
+    }
+    fn crate_limit(&self) -> usize {
+        ((self.config.scaled_crate_limit() * self.difficulty.settings().crate_limit_factor) as usize).clamp(1, MAX_CRATE_LIMIT)
+    }
+    fn remaining_crates(&self) -> u32 {
+        wave_crates(self.wave) - self.wave_spawned + self.crates.len() as u32
+    }
+    fn time_scale(&self) -> f32 {
+        let panic = if self.panic_slow {
+            PANIC_SLOW_SCALE
+        } else {
+            1.
+        };
+        panic * self.slow_mo
+    }
+    fn view(&self) -> Rect {
+        let size = Vec2::new(WIDTH, HEIGHT) / self.zoom;
+        let corner = self.camera + 0.5 * (Vec2::new(WIDTH, HEIGHT) - size);
+        Rect::new(corner.x, corner.y, size.x, size.y)
+    }
+    fn fit(&self, coords: Rect) -> Rect {
+        let frame = letterbox(self.window);
+        let scale = Vec2::new(coords.w / frame.w, coords.h / frame.h);
+        Rect::new(coords.x - frame.x * scale.x, coords.y - frame.y * scale.y, self.window.x * scale.x, self.window.y * scale.y)
+    }
+    fn mouse_pos(&self, ctx: &Context) -> Vec2 {
+        let frame = letterbox(self.window);
+        let Point2 { x, y } = ctx.mouse.position();
+        Vec2::new((x - frame.x) * WIDTH / frame.w, (y - frame.y) * HEIGHT / frame.h)
+    }
+    fn to_world(&self, screen: Vec2) -> Vec2 {
+        let view = self.view();
+        Vec2::new(view.x + screen.x / self.zoom, view.y + screen.y / self.zoom)
+    }
+    fn light(&self, pos: Vec2) -> f32 {
+        if !self.fog {
+            return 1.;
+        }
+        let lit = |centre: Vec2, radius: f32| 1. - (((centre - pos).length() - radius) / FOG_FADE).clamp(0., 1.);
+        self.bullets.iter()
+            .map(|b| lit(b.obj.pos, BULLET_LIGHT_RADIUS))
+            .fold(lit(self.ship.pos, SHIP_LIGHT_RADIUS), f32::max)
+    }
+    fn change_setting(&mut self, step: i32) {
+        match SettingsRow::ALL[self.settings_row] {
+            SettingsRow::Volume => {
+                self.config.volume = (self.config.volume + step as f32 * VOLUME_STEP).clamp(0., 1.);
+                for source in [&mut self.shoot_sound, &mut self.break_sound].into_iter().flatten() {
+                    source.set_volume(self.config.volume);
+                }
+            }
+            SettingsRow::Difficulty => {
+                let i = Difficulty::ALL.iter().position(|&d| d == self.config.difficulty).unwrap_or(0) as i32;
+                self.set_difficulty(Difficulty::ALL[(i + step).rem_euclid(Difficulty::ALL.len() as i32) as usize]);
+            }
+            SettingsRow::Bounce => {
+                self.config.bounce = !self.config.bounce;
+                self.bounce_edge = self.config.bounce;
+            }
+            SettingsRow::Density => {
+                self.config.density = (self.config.density + step as f32 * DENSITY_STEP).clamp(MIN_DENSITY, MAX_DENSITY);
+            }
+        }
+    }
+    fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.config.difficulty = difficulty;
+        self.difficulty = difficulty;
+    }
+    fn close_settings(&mut self, ctx: &Context) {
+        self.scene = Scene::Menu;
+        let saved = Config {
+            volume: self.config.volume,
+            difficulty: self.config.difficulty,
+            bounce: self.config.bounce,
+            density: self.config.density,
+            ..Config::load(ctx)
+        };
+        match saved.save(ctx) {
+            Ok(path) => println!("saved settings to {}", path.display()),
+            Err(e) => eprintln!("could not save settings: {e}"),
+        }
+    }
+    fn start_run(&mut self) {
+        self.scene = Scene::Playing;
+        self.run_difficulty = self.difficulty;
+        self.panic_run = self.panic_slow;
+        if self.args.record {
+            self.recording = Some(Replay::new(self.seed, self.difficulty, self.toggles(), self.config));
+        }
+    }
+    fn save_recording(&mut self, ctx: &Context) -> GameResult<Option<PathBuf>> {
+        let Some(recording) = self.recording.take() else {
+            return Ok(None);
+        };
+        let mut file = ctx.fs.create(REPLAY_PATH)?;
+        recording.write(&mut file)?;
+        Ok(Some(ctx.fs.user_config_dir().join(REPLAY_PATH.trim_start_matches('/'))))
+    }
+    fn dump_input_log(&self, ctx: &Context) -> GameResult<PathBuf> {
+        let mut file = ctx.fs.create(INPUT_LOG_PATH)?;
+        writeln!(file, "seed {}", self.seed)?;
+        for (step, input, rng_state) in &self.input_log {
+            writeln!(file, "{step} {:08x} {rng_state:016x}", input.0)?;
+        }
+        Ok(ctx.fs.user_config_dir().join(INPUT_LOG_PATH.trim_start_matches('/')))
+    }
+    fn save_high_score(&mut self, ctx: &Context) -> GameResult {
+        if self.playback.is_none() && self.score > self.high_scores.get(self.run_difficulty, self.panic_run) {
+            self.high_scores.set(self.run_difficulty, self.panic_run, self.score);
+            let mut file = ctx.fs.create(HIGH_SCORE_PATH)?;
+            self.high_scores.write(&mut file)?;
+        }
+        Ok(())
+    }
+    fn snapshot(&self) -> Snapshot<'_> {
+        Snapshot {
+            version: SAVE_VERSION,
+            seed: self.seed,
+            step: self.step,
+            play_time: self.play_time,
+            score: self.score,
+            combo: self.combo,
+            hp: self.hp,
+            invuln: self.invuln,
+            bombs: self.bombs,
+            run_difficulty: self.run_difficulty,
+            panic_run: self.panic_run,
+            wave: self.wave,
+            wave_spawned: self.wave_spawned,
+            wave_break: self.wave_break,
+            difficulty: self.difficulty,
+            weapon_mode: self.weapon_mode,
+            crate_spawn_time: self.crate_spawn_time,
+            enemy_spawn_time: self.enemy_spawn_time,
+            magnet_spawn_time: self.magnet_spawn_time,
+            fire_cooldown: self.fire_cooldown,
+            missile_cooldown: self.missile_cooldown,
+            rapid_fire: self.rapid_fire,
+            triple_shot: self.triple_shot,
+            shield: self.shield,
+            slow_mo: self.slow_mo,
+            bullet_time: self.bullet_time,
+            bullet_time_cooldown: self.bullet_time_cooldown,
+            dash: self.dash,
+            dash_cooldown: self.dash_cooldown,
+            ship: Cow::Borrowed(&self.ship),
+            bullets: Cow::Borrowed(&self.bullets),
+            missiles: Cow::Borrowed(&self.missiles),
+            enemy_bullets: Cow::Borrowed(&self.enemy_bullets),
+            crates: Cow::Borrowed(&self.crates),
+            enemies: Cow::Borrowed(&self.enemies),
+            boss: self.boss,
+            splinters: Cow::Borrowed(&self.splinters),
+            magnets: Cow::Borrowed(&self.magnets),
+            power_ups: Cow::Borrowed(&self.power_ups),
+        }
+    }
+    fn dump_state(&self, ctx: &Context) -> GameResult<PathBuf> {
+        let path = format!("/state-{}.json", timestamp());
+        let file = ctx.fs.create(&path)?;
+        serde_json::to_writer_pretty(file, &self.snapshot())
+            .map_err(|e| GameError::CustomError(e.to_string()))?;
+        Ok(ctx.fs.user_config_dir().join(path.trim_start_matches('/')))
+    }
+    fn save_state(&self, ctx: &Context) -> GameResult<PathBuf> {
+        let file = ctx.fs.create(SAVE_PATH)?;
+        serde_json::to_writer(file, &self.snapshot())
+            .map_err(|e| GameError::CustomError(e.to_string()))?;
+        Ok(ctx.fs.user_config_dir().join(SAVE_PATH.trim_start_matches('/')))
+    }
+    fn load_state(&mut self, ctx: &Context) -> GameResult {
+        let file = ctx.fs.open(SAVE_PATH)?;
+        let save: Snapshot = serde_json::from_reader(file)
+            .map_err(|e| GameError::CustomError(e.to_string()))?;
+        if save.version != SAVE_VERSION {
+            return Err(GameError::CustomError(format!("save version {} is not {SAVE_VERSION}", save.version)));
+        }
+        self.restore(save);
+        self.scene = Scene::Playing;
+        if self.recording.take().is_some() {
+            eprintln!("stopped recording: a loaded save cannot be replayed");
+        }
+        self.playback = None;
+        Ok(())
+    }
+    fn restore(&mut self, save: Snapshot) {
+        self.seed = save.seed;
+        self.rng = StdRng::seed_from_u64(save.seed.wrapping_add(save.step));
+        self.step = save.step;
+        self.play_time = save.play_time;
+        self.score = save.score;
+        self.combo = save.combo;
+        self.hp = save.hp;
+        self.invuln = save.invuln;
+        self.bombs = save.bombs;
+        self.run_difficulty = save.run_difficulty;
+        self.panic_run = save.panic_run;
+        self.wave = save.wave;
+        self.wave_spawned = save.wave_spawned;
+        self.wave_break = save.wave_break;
+        self.difficulty = save.difficulty;
+        self.weapon_mode = save.weapon_mode;
+        self.crate_spawn_time = save.crate_spawn_time;
+        self.enemy_spawn_time = save.enemy_spawn_time;
+        self.magnet_spawn_time = save.magnet_spawn_time;
+        self.fire_cooldown = save.fire_cooldown;
+        self.missile_cooldown = save.missile_cooldown;
+        self.rapid_fire = save.rapid_fire;
+        self.triple_shot = save.triple_shot;
+        self.shield = save.shield;
+        self.slow_mo = save.slow_mo;
+        self.bullet_time = save.bullet_time;
+        self.bullet_time_cooldown = save.bullet_time_cooldown;
+        self.dash = save.dash;
+        self.dash_cooldown = save.dash_cooldown;
+        self.ship = save.ship.into_owned();
+        self.bullets = save.bullets.into_owned();
+        self.missiles = save.missiles.into_owned();
+        self.enemy_bullets = save.enemy_bullets.into_owned();
+        self.crates = save.crates.into_owned();
+        self.enemies = save.enemies.into_owned();
+        self.boss = save.boss;
+        self.splinters = save.splinters.into_owned();
+        self.magnets = save.magnets.into_owned();
+        self.power_ups = save.power_ups.into_owned();
+        self.streaks.clear();
+        self.exhaust.clear();
+        self.input_log.clear();
+        self.selected = None;
+        self.accumulator = 0.;
+    }
+    fn selection(&self) -> Option<(&Obj, Option<f32>)> {
+        match self.selected? {
+            Selection::Ship => Some((&self.ship, None)),
+            Selection::Bullet(i) => self.bullets.get(i).map(|b| (&b.obj, Some(b.ttl))),
+            Selection::Crate(i) => self.crates.get(i).map(|c| (c, None)),
+            Selection::Splinter(i) => self.splinters.get(i).map(|s| (&s.obj, Some(s.ttl))),
+        }
+    }
+}
+
+const DENSITY: f32 = 1.;
+const MIN_DENSITY: f32 = 0.25;
+const MAX_DENSITY: f32 = 4.;
+const DENSITY_STEP: f32 = 0.25;
+const PANIC_SLOW_SCALE: f32 = 0.7;
+const BULLET_TIME_SCALE: f32 = 0.3;
+const BULLET_TIME_DURATION: f32 = 3.;
+const BULLET_TIME_COOLDOWN: f32 = 10.;
+const BULLET_TIME_RAMP: f32 = 6.;
+
+const CRATE_FLASH_TIME: f32 = 0.15;
+const CRATE_FLASH_IMPACT: f32 = 120.;
+const CRATE_FLASH_RADIUS: f32 = 64.;
+const CRATE_FADE_IN_TIME: f32 = 0.3;
+const CRATE_BLAST_RADIUS: f32 = 96.;
+const CRATE_BLAST_STRENGTH: f32 = 4000.;
+const CRATE_FLASH_COLOR: Color = Color::new(1., 0.35, 0.3, 1.);
+const CRATE_KNOCKBACK: f32 = 0.15;
+const TOUGH_CRATE_HP: u8 = 3;
+const TOUGH_CRATE_CHANCE_PER_WAVE: f64 = 0.05;
+const TOUGH_CRATE_CHANCE_MAX: f64 = 0.4;
+const TOUGH_CRATE_COLOR: Color = Color::new(0.75, 0.85, 1., 1.);
+const CRATE_LIMIT: usize = 200;
+const MAX_CRATE_LIMIT: usize = 5000;
+
+const ROT_SPEED: f32 = 5.53;
+const ACCELERATION: f32 = 150.;
+const SNAPPY_TOP_SPEED: f32 = 300.;
+const MAX_SHIP_SPEED: f32 = 450.;
+const SNAPPY_RESPONSE: f32 = 4.;
+const AUTO_BRAKE_DECEL: f32 = 40.;
+const CRATE_SPAWN_RATE: f32 = 0.65;
+const SPAWN_DISTRIBUTION: SpawnDistribution = SpawnDistribution::Uniform;
+const SPAWN_EDGE_BAND: f32 = 80.;
+const SPAWN_ATTEMPTS: u32 = 8;
+const SPAWN_RING_RADIUS: f32 = 300.;
+const SPAWN_SAFE_RADIUS: f32 = 160.;
+const SPAWN_SAFE_SHRINK: f32 = 0.;
+const SPAWN_SAFE_MIN_RADIUS: f32 = 0.;
+const CRATE_ROT_V_MIN: f32 = -3.;
+const CRATE_ROT_V_MAX: f32 = 3.;
+const INITIAL_CRATES: usize = 20;
+const CRATE_GRACE_PERIOD: f32 = 0.;
+const BULLET_SPEED: f32 = 470.;
+const FIRE_COOLDOWN: f32 = 0.15;
+const CLUSTER_COOLDOWN: f32 = 0.6;
+const SHOCKWAVE_COOLDOWN: f32 = 1.5;
+const MISSILE_SPEED: f32 = 350.;
+const MISSILE_TURN_RATE: f32 = 3.;
+const MISSILE_TTL: f32 = 4.;
+const MISSILE_COOLDOWN: f32 = 1.;
+const MISSILE_LIMIT: usize = 3;
+const MISSILE_LENGTH: f32 = 8.;
+const MISSILE_TRAIL_TTL: f32 = 0.25;
+const POWER_UP_CHANCE: f64 = 0.1;
+const POWER_UP_TTL: f32 = 10.;
+const POWER_UP_DRIFT: f32 = 0.5;
+const POWER_UP_RADIUS: f32 = 16.;
+const RAPID_FIRE_TIME: f32 = 8.;
+const RAPID_FIRE_FACTOR: f32 = 0.4;
+const RAPID_FIRE_COLOR: Color = Color::new(1., 0.8, 0.2, 1.);
+const TRIPLE_SHOT_TIME: f32 = 8.;
+const TRIPLE_SHOT_SPREAD: f32 = 0.15;
+const SHOTGUN_PELLETS: u32 = 5;
+const SHOTGUN_SPREAD: f32 = 0.35;
+const SHOTGUN_TTL_FACTOR: f32 = 0.3;
+const TRIPLE_SHOT_COLOR: Color = Color::new(0.3, 1., 0.4, 1.);
+const SHIELD_TIME: f32 = 6.;
+const SHIELD_COLOR: Color = Color::new(0.4, 0.7, 1., 0.8);
+const BULLET_TTL_MIN: f32 = 4.5;
+const BULLET_TTL_MAX: f32 = 6.2;
+const INPUT_LOG_LEN: usize = 600;
+const INPUT_LOG_PATH: &str = "/input_log.txt";
+const REPLAY_PATH: &str = "/replay.txt";
+const REPLAY_VERSION: u32 = 3;
+const SEED_VAR: &str = "SHOOTY_SEED";
+const HIGH_SCORE_PATH: &str = "/high_score.txt";
+const USAGE: &str = "usage: shooty [--width <px>] [--height <px>] [--seed <n>] [--difficulty easy|normal|hard] [--bounce] [--record | --replay]";
+const CONFIG_PATH: &str = "/config.toml";
+const SAVE_PATH: &str = "/save.json";
+const SAVE_VERSION: u32 = 15;
+const MASTER_VOLUME: f32 = 0.5;
+const VOLUME_STEP: f32 = 0.1;
+const DASH_IMPULSE: f32 = 500.;
+const DASH_TIME: f32 = 0.25;
+const DASH_COOLDOWN: f32 = 2.;
+const DASH_INVULN_TIME: f32 = 0.3;
+const DASH_TRAIL_TTL: f32 = 0.3;
+const EXHAUST_INTERVAL: f32 = 0.03;
+const EXHAUST_TTL: f32 = 0.4;
+const EXHAUST_SPEED: f32 = 120.;
+const EXHAUST_SPREAD: f32 = 30.;
+const WAVE_BASE_CRATES: u32 = INITIAL_CRATES as u32;
+const WAVE_CRATE_GROWTH: u32 = 8;
+const WAVE_RATE_FACTOR: f32 = 0.9;
+const WAVE_BREAK: f32 = 3.;
+const ENEMY_FIRST_WAVE: u32 = 3;
+const ENEMY_SPAWN_RATE: f32 = 12.;
+const ENEMY_LIMIT: usize = 4;
+const ENEMY_ACCELERATION: f32 = 90.;
+const ENEMY_MAX_SPEED: f32 = 160.;
+const ENEMY_MASS: f32 = 2.;
+const ENEMY_POINTS: u32 = 50;
+const ENEMY_FIRE_INTERVAL: f32 = 2.5;
+const ENEMY_BULLET_SPEED: f32 = 300.;
+const ENEMY_BULLET_TTL: f32 = 3.;
+const ENEMY_BULLET_DAMAGE: f32 = 10.;
+const BULLET_CANCEL_DIST: f32 = 8.;
+const ENEMY_BULLET_COLOR: Color = Color::new(1., 0.3, 0.6, 1.);
+const BOSS_WAVE_INTERVAL: u32 = 5;
+const BOSS_HP: f32 = 60.;
+const BOSS_SIZE: u8 = 6;
+const BOSS_RADIUS: f32 = 48.;
+const BOSS_MASS: f32 = 20.;
+const BOSS_SPEED: f32 = 40.;
+const BOSS_POINTS: u32 = 500;
+const BOSS_FIRE_INTERVAL: f32 = 2.;
+const BOSS_RING_SHOTS: u32 = 16;
+const BOSS_COLOR: Color = Color::new(0.8, 0.3, 1., 1.);
+const BOSS_BAR_SIZE: Vec2 = Vec2::new(400., 10.);
+const BOSS_BAR_POS: Vec2 = Vec2::new(0.5 * (WIDTH - BOSS_BAR_SIZE.x), MINIMAP_POS.y + MINIMAP_SIZE.y + 10.);
+const ENEMY_COLOR: Color = Color::new(1., 0.35, 0.3, 1.);
+const STAR_COUNT: usize = 200;
+const STAR_DEPTH_MIN: f32 = 0.2;
+const STAR_PARALLAX: f32 = 0.1;
+const STICK_DEADZONE: f32 = 0.2;
+const PHOTO_CAMERA_SPEED: f32 = 400.;
+const SHAKE_CRATE: f32 = 0.3;
+const SHAKE_HIT: f32 = 0.8;
+const BOMB_CHARGES: u32 = 3;
+const BOMB_FLASH_TIME: f32 = 0.4;
+const BOMB_PUSH: f32 = 300.;
+const SHAKE_DECAY: f32 = 2.5;
+const SHAKE_MAX_OFFSET: f32 = 12.;
+const CAMERA_FOLLOW_RATE: f32 = 4.;
+const ZOOM_STEP: f32 = 1.25;
+const ZOOM_MIN: f32 = 0.5;
+const ZOOM_MAX: f32 = 2.;
+const PICK_DIST: f32 = 24.;
+const BULLET_RADIUS: f32 = 8.;
+const BULLET_FADE_TIME: f32 = 0.5;
+const BULLET_COLOR: Color = Color::WHITE;
+const CLUSTER_COLOR: Color = Color::new(1., 0.75, 0.4, 1.);
+const SHOCKWAVE_COLOR: Color = Color::new(0.5, 0.9, 1., 1.);
+const BULLET_RECOIL: f32 = 2.;
+const TRAIL_LEN: usize = 5;
+const TRAIL_OPACITY: f32 = 0.5;
+const MUZZLE_FLASH_TIME: f32 = 0.06;
+const MUZZLE_FLASH_SCALE: f32 = 1.2;
+const MUZZLE_OFFSET: f32 = 20.;
+const CLUSTER_RECOIL: f32 = 40.;
+const SHOCKWAVE_RECOIL: f32 = 25.;
+const CLUSTER_CHILDREN: usize = 8;
+const CLUSTER_CHILD_SPEED: f32 = 320.;
+const BLAST_RADIUS: f32 = 90.;
+const SHOCKWAVE_PUSH: f32 = 300.;
+const WALL_RESTITUTION: f32 = 1.;
+const MAGNETIC_EDGE_DIST: f32 = 48.;
+const MAGNETIC_EDGE_BOOST: f32 = 200.;
+const STREAK_TTL: f32 = 0.25;
+const STREAK_LENGTH: f32 = 0.06;
+const MAGNET_SPAWN_RATE: f32 = 12.;
+const MAGNET_TTL: f32 = 6.;
+const MAGNET_RADIUS: f32 = 260.;
+const MAGNET_STRENGTH: f32 = 3e6;
+const MAGNET_DISPERSE_SPEED: f32 = 120.;
+const SHIP_LIGHT_RADIUS: f32 = 200.;
+const BULLET_LIGHT_RADIUS: f32 = 60.;
+const FOG_FADE: f32 = 160.;
+const DELTA: f32 = 1./60.;
+const MAX_FRAME_DELTA: f32 = 0.1;
+const SUBSTEP_THRESHOLD: f32 = 8.;
+const MAX_SUBSTEPS: u32 = 8;
+const PREDICTION_HORIZON: f32 = 1.5;
+const PREDICTION_RADIUS: f32 = 300.;
+const AIM_ASSIST_LENGTH: f32 = 250.;
+const AIM_ASSIST_OPACITY: f32 = 0.25;
+const THREAT_RADIUS: f32 = 200.;
+const SPLINTERS_PER_CRATE: usize = 4;
+const SPLINTER_DRAG: f32 = 1.;
+const SPLINTER_ANGULAR_DAMPING: f32 = 1.;
+const CRATE_ANGULAR_DAMPING: f32 = 1.;
+const SHIP_DRAG: f32 = 1.;
+const SPLINTER_LIMIT: usize = 500;
+const SPLINTER_HIT_RADIUS: f32 = 4.;
+const SPLINTER_KNOCKBACK: f32 = 0.05;
+const SPLINTER_COLLECT_DIST: f32 = 16.+8.;
+const SPLINTER_POINTS: u32 = 1;
+const CRATE_POINTS: u32 = 10;
+const COMBO_WINDOW: f32 = 2.;
+const COMBO_STEP: u32 = 5;
+const COMBO_MAX_MULTIPLIER: u32 = 8;
+const FULL_SIZE: u8 = 2;
+const OBJ_RADIUS: f32 = 16.;
+const OBJ_MASS: f32 = 1.;
+const SHIP_MASS: f32 = 4.;
+const SPLIT_SPEED: f32 = 40.;
+const SHIP_HP: f32 = 100.;
+const INVULN_TIME: f32 = 1.5;
+const INVULN_BLINK: f32 = 0.1;
+const INVULN_OPACITY: f32 = 0.3;
+const MINIMAP_SIZE: Vec2 = Vec2::new(160., 120.);
+const MINIMAP_POS: Vec2 = Vec2::new(0.5 * (WIDTH - MINIMAP_SIZE.x), 10.);
+const MINIMAP_BACKGROUND: Color = Color::new(0., 0., 0., 0.5);
+const CRATE_WARNING_THRESHOLD: f32 = 0.9;
+const CRATE_WARNING_BLINK: f32 = 0.25;
+const HUD_BACKDROP: Color = Color::new(0., 0., 0., 0.6);
+const HEALTH_BAR_SIZE: Vec2 = Vec2::new(200., 12.);
+const HEALTH_BAR_POS: Vec2 = Vec2::new(0.5 * (WIDTH - HEALTH_BAR_SIZE.x), HEIGHT - 40.);
+const COOLDOWN_BAR_SIZE: Vec2 = Vec2::new(200., 4.);
+const COOLDOWN_BAR_POS: Vec2 = Vec2::new(HEALTH_BAR_POS.x, HEALTH_BAR_POS.y + HEALTH_BAR_SIZE.y + 4.);
+const CRASH_DAMAGE: f32 = 0.1;
+#[cfg(feature = "gravity")]
+const GRAVITY_CONSTANT: f32 = 6.6743e-11 * 104e12 * 2.;
+
+fn is_threat(craet: &Obj, ship: &Obj) -> bool {
+    let d = ship.pos - craet.pos;
+    d.length_squared() < THREAT_RADIUS * THREAT_RADIUS && (craet.vel - ship.vel).dot(d) > 0.
+}
+
+fn draw_hud_text(ctx: &Context, canvas: &mut graphics::Canvas, text: Text, (anchor, offset): (Anchor, Vec2), color: Color) -> GameResult {
+    let size = text.measure(ctx)?;
+    canvas.draw(&text, DrawParam::new().dest(anchor.place(offset, size.into())).color(color));
+    Ok(())
+}
+
+fn read_gamepads(ctx: &Context) -> (Vec2, Vec2, bool) {
+    let deadzone = |v: Vec2| if v.length() < STICK_DEADZONE { Vec2::ZERO } else { v };
+    let mut stick = Vec2::ZERO;
+    let mut aim = Vec2::ZERO;
+    let mut fire = false;
+    for (_, pad) in ctx.gamepad.gamepads() {
+        let left = deadzone(Vec2::new(pad.value(Axis::LeftStickX), pad.value(Axis::LeftStickY)));
+        stick += Vec2::new(left.y, left.x);
+        aim += deadzone(Vec2::new(pad.value(Axis::RightStickX), -pad.value(Axis::RightStickY)));
+        fire |= pad.is_pressed(Button::South) || pad.is_pressed(Button::RightTrigger2);
+    }
+    (stick, aim, fire)
+}
+
+fn window_mode(config: &Config, fullscreen: bool) -> WindowMode {
+    WindowMode::default()
+        .dimensions(config.width, config.height)
+        .resizable(true)
+        .min_dimensions(0.25 * WIDTH, 0.25 * HEIGHT)
+        .fullscreen_type(if fullscreen { FullscreenType::Desktop } else { FullscreenType::Windowed })
+}
+
+fn parse_arg<T: std::str::FromStr>(name: &str, value: Option<String>) -> Option<T> {
+    let parsed = value.as_deref().and_then(|v| v.parse().ok());
+    if parsed.is_none() {
+        eprintln!("ignoring malformed {name} {value:?}");
+    }
+    parsed
+}
+
+fn letterbox(window: Vec2) -> Rect {
+    let scale = (window.x / WIDTH).min(window.y / HEIGHT);
+    let size = Vec2::new(WIDTH, HEIGHT) * scale;
+    Rect::new(0.5 * (window.x - size.x), 0.5 * (window.y - size.y), size.x, size.y)
+}
+
+fn seed() -> u64 {
+    std::env::var(SEED_VAR).ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or_else(rand::random)
+}
+
+fn timestamp() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+fn screenshot(ctx: &mut Context) -> GameResult<PathBuf> {
+    let frame = ctx.gfx.frame().clone();
+    let image = Image::new_canvas_image(ctx, ImageFormat::Rgba8UnormSrgb, frame.width(), frame.height(), 1);
+    let mut canvas = graphics::Canvas::from_image(ctx, image.clone(), Color::BLACK);
+    canvas.draw(&frame, DrawParam::new());
+    canvas.finish(ctx)?;
+
+    let path = format!("/screenshot-{}.png", timestamp());
+    image.encode(ctx, ImageEncodingFormat::Png, &path)?;
+    Ok(ctx.fs.user_config_dir().join(path.trim_start_matches('/')))
+}
+
+fn load_high_scores(ctx: &Context) -> HighScores {
+    let mut text = String::new();
+    match ctx.fs.open(HIGH_SCORE_PATH).map(|mut file| file.read_to_string(&mut text)) {
+        Ok(Ok(_)) => HighScores::parse(&text),
+        _ => HighScores::default(),
+    }
+}
+
+fn load_image(ctx: &Context, path: &str) -> GameResult<Image> {
+    Image::from_path(ctx, path).map_err(|e| {
+        eprintln!("could not load image {path}: {e}");
+        GameError::ResourceLoadError(format!("could not load image {path}: {e}"))
+    })
+}
+
+fn load_sound(ctx: &Context, path: &str, volume: f32) -> Option<Source> {
+    match Source::new(ctx, path) {
+        Ok(mut source) => {
+            source.set_volume(volume);
+            Some(source)
+        }
+        Err(e) => {
+            eprintln!("could not load sound {path}, playing without it: {e}");
+            None
+        }
+    }
+}
+
+fn load_shader(ctx: &Context, path: &str) -> Option<Shader> {
+    match ShaderBuilder::new().fragment_path(path).build(ctx) {
+        Ok(shader) => Some(shader),
+        Err(e) => {
+            eprintln!("could not load shader {path}, disabling it: {e}");
+            None
+        }
+    }
+}
+
+fn shoot(rng: &mut StdRng, ship: &Obj, rot: f32, speed: f32) -> Bullet {
+    let dir = angle_to_vec(rot);
+    let obj = Obj::from(ship.pos + dir * 20., ship.vel + dir * speed, rot);
+    obj.bullet(rng.random_range(BULLET_TTL_MIN ..= BULLET_TTL_MAX))
+}
+
+fn wave_crates(wave: u32) -> u32 {
+    WAVE_BASE_CRATES + WAVE_CRATE_GROWTH * (wave - 1)
+}
+
+fn starfield(rng: &mut StdRng) -> Vec<(Vec2, f32)> {
+    (0..STAR_COUNT)
+        .map(|_| (Vec2::new(rng.random_range(0. .. WIDTH), rng.random_range(0. .. HEIGHT)), rng.random_range(STAR_DEPTH_MIN .. 1.)))
+        .collect()
+}
+
+fn tough_crate_chance(wave: u32) -> f64 {
+    (TOUGH_CRATE_CHANCE_PER_WAVE * (wave - 1) as f64).min(TOUGH_CRATE_CHANCE_MAX)
+}
+
+fn spawn_safe_radius(play_time: f32) -> f32 {
+    let shrunk = SPAWN_SAFE_RADIUS - SPAWN_SAFE_SHRINK * (play_time - CRATE_GRACE_PERIOD).max(0.);
+    shrunk.max(SPAWN_SAFE_MIN_RADIUS)
+}
+
+fn bullet_hit<'a>(bullet: &Obj, targets: impl IntoIterator<Item = &'a Obj>, wrap: bool) -> Option<usize> {
+    targets.into_iter().position(|target| {
+        let collide_dist = target.radius + BULLET_RADIUS;
+        let d = if wrap { wrapped_delta(bullet.pos, target.pos) } else { bullet.pos - target.pos };
+        d.length_squared() < collide_dist * collide_dist
+    })
+}
+
+fn swept_hit<'a>(bullet: &Bullet, targets: impl IntoIterator<Item = &'a Obj>, wrap: bool) -> Option<usize> {
+    let motion = if wrap { wrapped_delta(bullet.obj.pos, bullet.prev) } else { bullet.obj.pos - bullet.prev };
+    let len_sq = motion.length_squared();
+    targets.into_iter().position(|target| {
+        let collide_dist = target.radius + BULLET_RADIUS;
+        let d = if wrap { wrapped_delta(bullet.obj.pos, target.pos) } else { bullet.obj.pos - target.pos };
+        let t = if len_sq > 0. { (d.dot(motion) / len_sq).clamp(0., 1.) } else { 0. };
+        (d - t * motion).length_squared() < collide_dist * collide_dist
+    })
+}
+
+fn substeps(max_displacement: f32) -> u32 {
+    ((max_displacement / SUBSTEP_THRESHOLD).ceil() as u32).clamp(1, MAX_SUBSTEPS)
+}
+
+fn for_substeps(dt: f32, max_speed: f32, sub_stepping: bool, mut physics: impl FnMut(f32)) {
+    let n = if sub_stepping { substeps(max_speed * dt) } else { 1 };
+    for _ in 0..n {
+        physics(dt / n as f32);
+    }
+}
+
+// Returns the position before wrapping if the object crossed an edge
+fn integrate(obj: &mut Obj, dt: f32, bounce_edge: bool, magnetic_edge: bool) -> Option<Vec2> {
+    #[cfg(feature = "gravity")]
+    {
+        obj.grav_accel = Vec2::ZERO;
+    }
+    obj.pos += obj.vel * dt;
+    obj.rot += obj.rot_v * dt;
+    if bounce_edge {
+        obj.bounce_edges(WALL_RESTITUTION);
+        return None;
+    }
+    if magnetic_edge {
+        const D: f32 = MAGNETIC_EDGE_DIST;
+        if (obj.pos.x < D && obj.vel.x < 0.) || (obj.pos.x >= WIDTH-D && obj.vel.x > 0.) {
+            obj.pos.x += obj.vel.x.signum() * MAGNETIC_EDGE_BOOST * dt;
+        }
+        if (obj.pos.y < D && obj.vel.y < 0.) || (obj.pos.y >= HEIGHT-D && obj.vel.y > 0.) {
+            obj.pos.y += obj.vel.y.signum() * MAGNETIC_EDGE_BOOST * dt;
+        }
+    }
+    let old_pos = obj.pos;
+    obj.pos.x = obj.pos.x.rem_euclid(WIDTH);
+    obj.pos.y = obj.pos.y.rem_euclid(HEIGHT);
+    (obj.pos != old_pos).then_some(old_pos)
+}
+
+fn wrapped_delta(a: Vec2, b: Vec2) -> Vec2 {
+    let d = a - b;
+    Vec2::new(
+        (d.x + 0.5 * WIDTH).rem_euclid(WIDTH) - 0.5 * WIDTH,
+        (d.y + 0.5 * HEIGHT).rem_euclid(HEIGHT) - 0.5 * HEIGHT,
+    )
+}
+
+fn wrap_offsets(obj: &Obj, wrap: bool) -> impl Iterator<Item = Vec2> {
+    let r = obj.radius * SQRT_2;
+    let offset = |p: f32, size: f32| {
+        if !wrap {
+            None
+        } else if p < r {
+            Some(size)
+        } else if p >= size - r {
+            Some(-size)
+        } else {
+            None
+        }
+    };
+    let xs = iter::once(0.).chain(offset(obj.pos.x, WIDTH));
+    let ys = iter::once(0.).chain(offset(obj.pos.y, HEIGHT));
+    xs.flat_map(move |x| ys.clone().map(move |y| Vec2::new(x, y)))
+}
+
+fn clamp_delta(delta: Duration) -> f32 {
+    delta.as_secs_f32().min(MAX_FRAME_DELTA)
+}
+
+#[cfg(feature = "gravity")]
+fn resolve_crates(crates: &mut [Obj], wrap: bool) {
+    crates.compare_self_mut(|a, b| a.knock(b, wrap));
+}
+
+struct Grid {
+    cells: std::collections::HashMap<(i32, i32), Vec<usize>>,
+    wrap: bool,
+}
+
+impl Grid {
+    const CELL: f32 = 32.;
+    const COLUMNS: i32 = (WIDTH / Self::CELL) as i32;
+    const ROWS: i32 = (HEIGHT / Self::CELL) as i32;
+
+    fn new<'a>(objs: impl IntoIterator<Item = &'a Obj>, wrap: bool) -> Self {
+        let mut grid = Grid { cells: Default::default(), wrap };
+        for (i, obj) in objs.into_iter().enumerate() {
+            grid.cells.entry(grid.cell(obj.pos)).or_default().push(i);
+        }
+        grid
+    }
+    fn cell(&self, pos: Vec2) -> (i32, i32) {
+        let (x, y) = ((pos.x / Self::CELL).floor() as i32, (pos.y / Self::CELL).floor() as i32);
+        if self.wrap {
+            (x.clamp(0, Self::COLUMNS - 1), y.clamp(0, Self::ROWS - 1))
+        } else {
+            (x, y)
+        }
+    }
+    fn near(&self, pos: Vec2) -> impl Iterator<Item = usize> + '_ {
+        self.around(pos, 1)
+    }
+    fn within(&self, pos: Vec2, radius: f32) -> impl Iterator<Item = usize> + '_ {
+        self.around(pos, (radius / Self::CELL).ceil() as i32)
+    }
+    fn around(&self, pos: Vec2, reach: i32) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = self.cell(pos);
+        let span = move |c: i32, size: i32| if self.wrap && 2 * reach >= size { 0 ..= size - 1 } else { c - reach ..= c + reach };
+        let neighbour = move |x: i32, size: i32| if self.wrap { x.rem_euclid(size) } else { x };
+        span(x, Self::COLUMNS)
+            .flat_map(move |x| span(y, Self::ROWS).map(move |y| (neighbour(x, Self::COLUMNS), neighbour(y, Self::ROWS))))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+    }
+}
+
+#[cfg(not(feature = "gravity"))]
+fn resolve_crates(crates: &mut [Obj], wrap: bool) {
+    let grid = Grid::new(crates.iter(), wrap);
+    let mut pairs = Vec::new();
+    for (i, c) in crates.iter().enumerate() {
+        pairs.extend(grid.near(c.pos).filter(|&j| j > i).map(|j| (i, j)));
+    }
+    pairs.sort_unstable();
+    pairs.dedup();
+
+    for (i, j) in pairs {
+        let (a, b) = crates.split_at_mut(j);
+        a[i].knock(&mut b[0], wrap);
+    }
+}
+
+fn cancel_bullets(bullets: &mut Vec<Bullet>, enemy_bullets: &mut Vec<Bullet>, wrap: bool) {
+    let grid = Grid::new(enemy_bullets.iter().map(|b| &b.obj), wrap);
+    let mut hit = vec![false; enemy_bullets.len()];
+    bullets.retain(|bullet| {
+        let other = grid.near(bullet.obj.pos).find(|&j| {
+            let other = &enemy_bullets[j].obj;
+            let d = if wrap { wrapped_delta(bullet.obj.pos, other.pos) } else { bullet.obj.pos - other.pos };
+            !hit[j] && d.length_squared() < BULLET_CANCEL_DIST * BULLET_CANCEL_DIST
+        });
+        if let Some(j) = other {
+            hit[j] = true;
+        }
+        other.is_none()
+    });
+    let mut hit = hit.into_iter();
+    enemy_bullets.retain(|_| !hit.next().unwrap_or(false));
+}
+
+fn expire(rng: &mut StdRng, bullets: &mut Vec<Bullet>, dt: f32) {
+    let mut children = Vec::new();
+    bullets.retain_mut(|bullet| {
+        bullet.ttl -= dt;
+        if bullet.ttl <= 0. && bullet.kind == BulletKind::Cluster {
+            for n in 0..CLUSTER_CHILDREN {
+                let angle = bullet.obj.rot + n as f32 * TAU / CLUSTER_CHILDREN as f32;
+                let obj = Obj::from(bullet.obj.pos, bullet.obj.vel + angle_to_vec(angle) * CLUSTER_CHILD_SPEED, angle);
+                children.push(obj.bullet(rng.random_range(1.2 .. 1.8)));
+            }
+        }
+        bullet.ttl > 0.
+    });
+    bullets.extend(children);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn destroy_crate(rng: &mut StdRng, crates: &mut Vec<Obj>, splinters: &mut Vec<Bullet>, per_crate: usize, power_ups: &mut Vec<PowerUp>, c: usize, push: Vec2, wrap: bool) -> u32 {
+    let crat = crates.swap_remove(c);
+    if rng.random_bool(POWER_UP_CHANCE) {
+        let kind = PowerKind::ALL[rng.random_range(0 .. PowerKind::ALL.len())];
+        let obj = Obj::from(crat.pos, crat.vel * POWER_UP_DRIFT, 0.);
+        power_ups.push(PowerUp { obj, ttl: POWER_UP_TTL, kind });
+    }
+    for other in crates.iter_mut() {
+        let d = if wrap { wrapped_delta(other.pos, crat.pos) } else { other.pos - crat.pos };
+        let dist_sq = d.length_squared();
+        if dist_sq < CRATE_FLASH_RADIUS * CRATE_FLASH_RADIUS {
+            other.flash = CRATE_FLASH_TIME;
+        }
+        if dist_sq < CRATE_BLAST_RADIUS * CRATE_BLAST_RADIUS {
+            other.vel += CRATE_BLAST_STRENGTH * other.inv_mass() * d / dist_sq.max(OBJ_RADIUS * OBJ_RADIUS);
+        }
+    }
+    break_crate(rng, crates, splinters, per_crate, crat, push)
+}
+
+// Destroys a crate along with every half it splits into. The halves are always
+// pushed onto the end of `crates`, so indices below `c` stay valid.
+#[allow(clippy::too_many_arguments)]
+fn destroy_crate_fully(rng: &mut StdRng, crates: &mut Vec<Obj>, splinters: &mut Vec<Bullet>, per_crate: usize, power_ups: &mut Vec<PowerUp>, c: usize, push: Vec2, wrap: bool) -> u32 {
+    let mut pending = vec![c];
+    let mut points = 0;
+    while let Some(c) = pending.pop() {
+        let remaining = crates.len() - 1;
+        points += destroy_crate(rng, crates, splinters, per_crate, power_ups, c, push, wrap);
+        pending.extend(remaining..crates.len());
+    }
+    points
+}
+
+fn break_crate(rng: &mut StdRng, crates: &mut Vec<Obj>, splinters: &mut Vec<Bullet>, per_crate: usize, crat: Obj, push: Vec2) -> u32 {
+    if crat.size > 1 {
+        let side = push.try_normalize().unwrap_or(angle_to_vec(crat.rot)).perp();
+        for dir in [side, -side] {
+            let mut half = crat.pushed(rng, dir.x * crat.radius / 2., dir.y * crat.radius / 2., dir.x * SPLIT_SPEED, dir.y * SPLIT_SPEED);
+            half.size -= 1;
+            half.radius /= 2.;
+            half.mass /= 2.;
+            crates.push(half);
+        }
+        0
+    } else {
+        shatter(rng, splinters, per_crate, crat, push);
+        CRATE_POINTS
+    }
+}
+
+fn shatter(rng: &mut StdRng, splinters: &mut Vec<Bullet>, count: usize, mut crat: Obj, push: Vec2) {
+    const D: f32 = 8.;
+    const DV: f32 = 50.;
+    crat.vel += 0.4 * push;
+    let excess = (splinters.len() + count).saturating_sub(SPLINTER_LIMIT);
+    splinters.drain(..excess.min(splinters.len()));
+    for n in 0..count {
+        let dir = angle_to_vec(n as f32 * TAU / count as f32);
+        splinters.push(crat.pushed(rng, D * dir.x, D * dir.y, DV * dir.x, DV * dir.y).bullet(rng.random_range(1.6 .. 4.2)));
+    }
+}
+
+pub fn angle_to_vec(angle: f32) -> Vec2 {
+    let (sin, cos) = angle.sin_cos();
+    Vec2::new(cos, sin)
+}
+
+impl event::EventHandler<ggez::GameError> for MainState {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        if ctx.keyboard.is_key_just_pressed(self.controls.quit) {
+            if self.scene == Scene::Settings {
+                self.close_settings(ctx);
+            } else {
+                ctx.request_quit();
+            }
+            return Ok(());
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_fullscreen) {
+            match ctx.gfx.set_mode(window_mode(&self.config, !self.fullscreen)) {
+                Ok(()) => self.fullscreen = !self.fullscreen,
+                Err(e) => eprintln!("could not switch fullscreen: {e}"),
+            }
+            self.window = Vec2::from(ctx.gfx.drawable_size());
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_photo_mode) {
+            self.photo_mode = !self.photo_mode;
+            self.camera = Vec2::ZERO;
+        }
+        if self.photo_mode {
+            let mut pan = Vec2::ZERO;
+            if ctx.keyboard.is_key_pressed(self.controls.camera_left) {
+                pan.x -= 1.;
+            }
+            if ctx.keyboard.is_key_pressed(self.controls.camera_right) {
+                pan.x += 1.;
+            }
+            if ctx.keyboard.is_key_pressed(self.controls.camera_up) {
+                pan.y -= 1.;
+            }
+            if ctx.keyboard.is_key_pressed(self.controls.camera_down) {
+                pan.y += 1.;
+            }
+            self.camera += pan * PHOTO_CAMERA_SPEED * clamp_delta(ctx.time.delta());
+            if ctx.keyboard.is_key_just_pressed(self.controls.screenshot) {
+                self.screenshot_requested = true;
+            }
+            return Ok(());
+        }
+        match self.scene {
+            Scene::Menu => {
+                if ctx.keyboard.is_key_just_pressed(self.controls.cycle_difficulty) {
+                    self.set_difficulty(self.config.difficulty.next());
+                }
+                if ctx.keyboard.is_key_just_pressed(self.controls.open_settings) {
+                    self.scene = Scene::Settings;
+                    self.settings_row = 0;
+                }
+                if ctx.keyboard.is_key_just_pressed(self.controls.confirm) {
+                    self.start_run();
+                }
+                return Ok(());
+            }
+            Scene::Settings => {
+                let rows = SettingsRow::ALL.len();
+                if ctx.keyboard.is_key_just_pressed(self.controls.menu_up) {
+                    self.settings_row = (self.settings_row + rows - 1) % rows;
+                }
+                if ctx.keyboard.is_key_just_pressed(self.controls.menu_down) {
+                    self.settings_row = (self.settings_row + 1) % rows;
+                }
+                if ctx.keyboard.is_key_just_pressed(self.controls.menu_left) {
+                    self.change_setting(-1);
+                }
+                if ctx.keyboard.is_key_just_pressed(self.controls.menu_right) {
+                    self.change_setting(1);
+                }
+                if ctx.keyboard.is_key_just_pressed(self.controls.confirm) {
+                    self.close_settings(ctx);
+                }
+                return Ok(());
+            }
+            Scene::GameOver => {
+                if ctx.keyboard.is_key_just_pressed(self.controls.confirm) {
+                    self.reset();
+                    self.start_run();
+                }
+                return Ok(());
+            }
+            Scene::Playing => (),
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.pause) {
+            self.paused = !self.paused;
+            self.focus_paused = false;
+        }
+        if self.paused {
+            return Ok(());
+        }
+
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_velocities) {
+            self.show_vel = !self.show_vel;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_fog) {
+            self.fog = !self.fog;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_trajectories) {
+            self.show_trajectories = !self.show_trajectories;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_aim_assist) {
+            self.aim_assist = !self.aim_assist;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.cycle_hud_layout) {
+            let i = HudLayout::PRESETS.iter().position(|&l| l == self.hud_layout).unwrap_or(0);
+            self.hud_layout = HudLayout::PRESETS[(i + 1) % HudLayout::PRESETS.len()];
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.cycle_palette) {
+            let i = Palette::PRESETS.iter().position(|p| p.name == self.palette.name).unwrap_or(0);
+            self.palette = Palette::PRESETS[(i + 1) % Palette::PRESETS.len()];
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_debug) {
+            self.debug = !self.debug;
+            self.selected = None;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_diagnostics) {
+            self.diagnostics = !self.diagnostics;
+        }
+        if self.debug && ctx.keyboard.is_key_just_pressed(self.controls.dump_input_log) {
+            match self.dump_input_log(ctx) {
+                Ok(path) => println!("wrote input log to {}", path.display()),
+                Err(e) => eprintln!("could not write input log: {e}"),
+            }
+        }
+        if self.debug && ctx.keyboard.is_key_just_pressed(self.controls.dump_state) {
+            match self.dump_state(ctx) {
+                Ok(path) => println!("wrote state to {}", path.display()),
+                Err(e) => eprintln!("could not write state: {e}"),
+            }
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.save_state) {
+            match self.save_state(ctx) {
+                Ok(path) => println!("saved game to {}", path.display()),
+                Err(e) => eprintln!("could not save game: {e}"),
+            }
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.load_state) {
+            match self.load_state(ctx) {
+                Ok(()) => println!("loaded game"),
+                Err(e) => eprintln!("could not load game: {e}"),
+            }
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_mouse_aim) {
+            self.mouse_aim = !self.mouse_aim;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_crt) {
+            self.crt = !self.crt;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_bloom) {
+            self.bloom = !self.bloom;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_auto_resume) {
+            self.auto_resume = !self.auto_resume;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.toggle_follow_camera) {
+            self.follow_camera = !self.follow_camera;
+            self.camera = Vec2::ZERO;
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.zoom_in) {
+            self.zoom = (self.zoom * ZOOM_STEP).min(ZOOM_MAX);
+        }
+        if ctx.keyboard.is_key_just_pressed(self.controls.zoom_out) {
+            self.zoom = (self.zoom / ZOOM_STEP).max(ZOOM_MIN);
+        }
+        if self.follow_camera {
+            let target = self.ship.pos - 0.5 * Vec2::new(WIDTH, HEIGHT);
+            let t = 1. - (-CAMERA_FOLLOW_RATE * clamp_delta(ctx.time.delta())).exp();
+            self.camera = self.camera.lerp(target, t);
+        }
+
+        let mut input = Input::read(ctx, &self.controls);
+        if self.mouse_aim && self.live_aim() {
+            let d = self.to_world(self.mouse_pos(ctx)) - self.ship.pos;
+            self.ship.rot = d.y.atan2(d.x);
+            if ctx.mouse.button_just_pressed(MouseButton::Left) {
+                input.0 |= Input::FIRE;
+            }
+            if ctx.mouse.button_pressed(MouseButton::Left) {
+                input.0 |= Input::FIRE_HELD;
+            }
+        }
+        let (stick, aim, fire) = read_gamepads(ctx);
+        self.stick = if self.live_aim() { stick } else { Vec2::ZERO };
+        if aim != Vec2::ZERO && self.live_aim() {
+            self.ship.rot = aim.y.atan2(aim.x);
+        }
+        if fire {
+            input.0 |= Input::FIRE_HELD;
+        }
+        self.queued_input = Input(self.queued_input.0 & Input::pressed_mask() | input.0);
+        self.accumulator += clamp_delta(ctx.time.delta());
+        while self.accumulator >= DELTA && self.scene == Scene::Playing {
+            self.accumulator -= DELTA;
+            let input = self.queued_input;
+            self.queued_input = Input(input.0 & !Input::pressed_mask());
+            let recorded = self.playback.as_ref().map(|r| r.inputs.get(self.step as usize).copied());
+            let input = match recorded {
+                Some(Some(recorded)) => recorded,
+                Some(None) => {
+                    println!("replay finished");
+                    self.playback = None;
+                    input
+                }
+                None => input,
+            };
+            self.step(input);
+        }
+        if self.scene == Scene::GameOver {
+            match self.save_recording(ctx) {
+                Ok(Some(path)) => println!("wrote replay to {}", path.display()),
+                Ok(None) => (),
+                Err(e) => eprintln!("could not write replay: {e}"),
+            }
+            if let Err(e) = self.save_high_score(ctx) {
+                eprintln!("could not save high score: {e}");
+            }
+        }
+
+        for sound in self.sounds.drain(..) {
+            let source = match sound {
+                Sound::Shoot => &mut self.shoot_sound,
+                Sound::Break => &mut self.break_sound,
+            };
+            if let Some(source) = source {
+                if let Err(e) = source.play_detached(ctx) {
+                    eprintln!("could not play {sound:?} sound: {e}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        let crt_shader = self.crt_shader.as_ref().filter(|_| self.crt);
+        let mut view = self.view();
+        let shake = if self.photo_mode { 0. } else { SHAKE_MAX_OFFSET * self.shake.min(1.).powi(2) };
+        view.translate(Vec2::new(self.shake_rng.random_range(-shake ..= shake), self.shake_rng.random_range(-shake ..= shake)));
+        let bloom_shader = self.bloom_shader.as_ref().filter(|_| self.bloom);
+        let wrap = !self.bounce_edge;
+
+        if bloom_shader.is_some() {
+            let mut glow = graphics::Canvas::from_screen_image(ctx, &mut self.glow, Color::from_rgba(0, 0, 0, 0));
+            glow.set_screen_coordinates(self.fit(view));
+            glow.set_scissor_rect(letterbox(self.window))?;
+            for bullet in &self.bullets {
+                for offset in wrap_offsets(&bullet.obj, wrap) {
+                    glow.draw(&self.bullet_img, bullet.draw_param().dest(bullet.obj.pos + offset));
+                }
+            }
+            for splinter in &self.splinters {
+                for offset in wrap_offsets(&splinter.obj, wrap) {
+                    glow.draw(&self.splinter_img, splinter.draw_param().dest(splinter.obj.pos + offset));
+                }
+            }
+            glow.finish(ctx)?;
+        }
+
+        let mut canvas = if crt_shader.is_some() {
+            graphics::Canvas::from_screen_image(ctx, &mut self.scene_img, Color::BLACK)
+        } else {
+            graphics::Canvas::from_frame(ctx, Color::BLACK)
+        };
+        canvas.set_screen_coordinates(self.fit(view));
+        canvas.set_scissor_rect(letterbox(self.window))?;
+
+        let mut starfield = graphics::MeshBuilder::new();
+        let tiles = |from: f32, span: f32, size: f32| (0 ..= (span / size).ceil() as i32).map(move |n| from + n as f32 * size);
+        for &(pos, depth) in &self.stars {
+            let p = pos - self.travel * depth * STAR_PARALLAX - Vec2::new(view.x, view.y);
+            let p = Vec2::new(p.x.rem_euclid(WIDTH), p.y.rem_euclid(HEIGHT));
+            let size = 2. * depth;
+            for x in tiles(view.x + p.x - WIDTH, view.w, WIDTH) {
+                for y in tiles(view.y + p.y - HEIGHT, view.h, HEIGHT) {
+                    starfield.rectangle(DrawMode::fill(), Rect::new(x, y, size, size), opacity(depth))?;
+                }
+            }
+        }
+        canvas.draw(&Mesh::from_data(ctx, starfield.build()), DrawParam::new());
+
+        if self.aim_assist {
+            let dir = angle_to_vec(self.ship.rot);
+            let nose = self.ship.pos + dir * MUZZLE_OFFSET;
+            let aim = (self.ship.vel + dir * self.config.bullet_speed).try_normalize().unwrap_or(dir);
+            let line = Mesh::new_line(ctx, &[nose, nose + aim * AIM_ASSIST_LENGTH], 1., opacity(AIM_ASSIST_OPACITY))?;
+            canvas.draw(&line, DrawParam::new());
+        }
+        let blink = (self.invuln / INVULN_BLINK) as u32 % 2 == 1;
+        let ship_color = if blink { opacity(INVULN_OPACITY) } else { Color::WHITE };
+        for offset in wrap_offsets(&self.ship, wrap) {
+            canvas.draw(&self.ship_img, self.ship.draw_param().dest(self.ship.pos + offset).color(ship_color));
+        }
+        if self.muzzle_flash > 0. {
+            let nose = self.ship.pos + angle_to_vec(self.ship.rot) * MUZZLE_OFFSET;
+            let param = self.ship.draw_param().scale(Vec2::splat(MUZZLE_FLASH_SCALE)).color(opacity(self.muzzle_flash / MUZZLE_FLASH_TIME));
+            for offset in wrap_offsets(&self.ship, wrap) {
+                canvas.draw(&self.bullet_img, param.dest(nose + offset));
+            }
+        }
+        self.bullet_batch.clear();
+        for bullet in &self.bullets {
+            for param in bullet.trail_params(bullet.draw_param()) {
+                self.bullet_batch.push(param);
+            }
+            for offset in wrap_offsets(&bullet.obj, wrap) {
+                self.bullet_batch.push(bullet.draw_param().dest(bullet.obj.pos + offset));
+            }
+        }
+        for bullet in &self.enemy_bullets {
+            let param = bullet.draw_param();
+            let param = param.color(Color { a: param.color.a, ..ENEMY_BULLET_COLOR });
+            for param in bullet.trail_params(param) {
+                self.bullet_batch.push(param);
+            }
+            for offset in wrap_offsets(&bullet.obj, wrap) {
+                self.bullet_batch.push(param.dest(bullet.obj.pos + offset));
+            }
+        }
+        canvas.draw(&self.bullet_batch, DrawParam::new());
+        for missile in &self.missiles {
+            for offset in wrap_offsets(&missile.obj, wrap) {
+                canvas.draw(&self.missile_img, missile.obj.draw_param().dest(missile.obj.pos + offset));
+            }
+        }
+        let crate_params: Vec<_> = self.crates.iter()
+            .flat_map(|craet| {
+                let param = craet.draw_param().color(crate_color(craet, self.light(craet.pos)));
+                wrap_offsets(craet, wrap).map(move |offset| param.dest(craet.pos + offset))
+            })
+            .collect();
+        self.crate_batch.set(crate_params);
+        canvas.draw(&self.crate_batch, DrawParam::new());
+        for craet in &self.crates {
+            if self.palette.shape_cues && is_threat(craet, &self.ship) {
+                let outline = Mesh::new_circle(ctx, DrawMode::stroke(2.), craet.pos, 20., 1., self.palette.threat)?;
+                canvas.draw(&outline, DrawParam::new());
+            }
+            if self.show_trajectories && craet.vel != Vec2::ZERO && (craet.pos - self.ship.pos).length_squared() < PREDICTION_RADIUS * PREDICTION_RADIUS {
+                let line = Mesh::new_line(ctx, &[craet.pos, craet.pos + craet.vel * PREDICTION_HORIZON], 1., opacity(0.3))?;
+                canvas.draw(&line, DrawParam::new());
+            }
+            if self.show_vel {
+                let line = Mesh::new_line(ctx, &[craet.pos, craet.pos + craet.vel], 2., self.palette.velocity)?;
+                canvas.draw(&line, DrawParam::new());
+                #[cfg(feature = "gravity")] {
+                    let grav_line = Mesh::new_line(ctx, &[craet.pos + craet.vel, craet.pos + craet.vel + craet.grav_accel], 2., self.palette.gravity)?;
+                    canvas.draw(&grav_line, DrawParam::new());
+                }
+            }
+        }
+        for enemy in &self.enemies {
+            for offset in wrap_offsets(&enemy.obj, wrap) {
+                canvas.draw(&self.ship_img, enemy.obj.draw_param().dest(enemy.obj.pos + offset).color(ENEMY_COLOR));
+            }
+        }
+        if let Some(boss) = &self.boss {
+            for offset in wrap_offsets(&boss.obj, wrap) {
+                canvas.draw(&self.ship_img, boss.obj.draw_param().dest(boss.obj.pos + offset).color(BOSS_COLOR));
+            }
+        }
+        let splinter_params: Vec<_> = self.splinters.iter()
+            .flat_map(|splinter| {
+                let mut param = splinter.draw_param();
+                param.color.a *= self.light(splinter.obj.pos);
+                wrap_offsets(&splinter.obj, wrap).map(move |offset| param.dest(splinter.obj.pos + offset))
+            })
+            .collect();
+        self.splinter_batch.set(splinter_params);
+        canvas.draw(&self.splinter_batch, DrawParam::new());
+        for power_up in &self.power_ups {
+            let color = power_up.kind.color();
+            let param = power_up.obj.draw_param().color(Color { a: color.a * fade_alpha(power_up.ttl, BULLET_FADE_TIME), ..color });
+            for offset in wrap_offsets(&power_up.obj, wrap) {
+                canvas.draw(&self.power_up_img, param.dest(power_up.obj.pos + offset));
+            }
+        }
+        if self.shield > 0. {
+            let color = Color { a: SHIELD_COLOR.a * fade_alpha(self.shield, BULLET_FADE_TIME), ..SHIELD_COLOR };
+            let param = DrawParam::new()
+                .offset(Point2::from(Vec2::new(0.5, 0.5)))
+                .color(color);
+            for offset in wrap_offsets(&self.ship, wrap) {
+                canvas.draw(&self.power_up_img, param.dest(self.ship.pos + offset));
+            }
+        }
+        for magnet in &self.magnets {
+            let pulse = 12. + 4. * (magnet.ttl * 6.).sin();
+            let core = Mesh::new_circle(ctx, DrawMode::fill(), magnet.pos, pulse, 0.5, self.palette.magnet)?;
+            canvas.draw(&core, DrawParam::new());
+            let range = Mesh::new_circle(ctx, DrawMode::stroke(1.), magnet.pos, MAGNET_RADIUS, 1., Color { a: 0.25, ..self.palette.magnet })?;
+            canvas.draw(&range, DrawParam::new());
+        }
+        if !self.exhaust.is_empty() {
+            let mut exhaust = graphics::MeshBuilder::new();
+            for particle in &self.exhaust {
+                exhaust.circle(DrawMode::fill(), particle.obj.pos, 2., 0.5, opacity(particle.ttl / EXHAUST_TTL))?;
+            }
+            canvas.draw(&Mesh::from_data(ctx, exhaust.build()), DrawParam::new());
+        }
+        for streak in &self.streaks {
+            let tail = streak.obj.pos - streak.obj.vel * STREAK_LENGTH;
+            let line = Mesh::new_line(ctx, &[tail, streak.obj.pos], 2., opacity(streak.ttl / STREAK_TTL))?;
+            canvas.draw(&line, DrawParam::new());
+        }
+
+        if let Some(shader) = bloom_shader {
+            canvas.set_shader(shader);
+            canvas.set_blend_mode(BlendMode::ADD);
+            let coords = self.fit(view);
+            canvas.draw(&self.glow.image(ctx), DrawParam::new().dest(coords.point()).scale(Vec2::new(coords.w / self.window.x, coords.h / self.window.y)));
+            canvas.set_default_shader();
+            canvas.set_blend_mode(BlendMode::ALPHA);
+        }
+
+        if !self.photo_mode {
+            if let Some((obj, _)) = self.selection() {
+                let highlight = Mesh::new_circle(ctx, DrawMode::stroke(2.), obj.pos, PICK_DIST, 1., self.palette.highlight)?;
+                canvas.draw(&highlight, DrawParam::new());
+            }
+            canvas.set_screen_coordinates(self.fit(Rect::new(0., 0., WIDTH, HEIGHT)));
+            if self.bomb_flash > 0. {
+                let flash = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect::new(0., 0., WIDTH, HEIGHT), opacity(self.bomb_flash / BOMB_FLASH_TIME))?;
+                canvas.draw(&flash, DrawParam::new());
+            }
+            if let Some((obj, ttl)) = self.selection() {
+
+                let mut info = format!(
+                    "{:?}\npos: ({:.1}, {:.1})\nvel: ({:.1}, {:.1})\nrot: {:.2}\nrot_v: {:.2}",
+                    self.selected.unwrap(), obj.pos.x, obj.pos.y, obj.vel.x, obj.vel.y, obj.rot, obj.rot_v,
+                );
+                if let Some(ttl) = ttl {
+                    info += &format!("\nttl: {ttl:.2}");
+                }
+                draw_hud_text(ctx, &mut canvas, Text::new(info), self.hud_layout.inspector, self.palette.highlight)?;
+            }
+
+            let mut difficulty = format!("{} | {}", self.difficulty.name(), self.weapon_mode.name());
+            if self.time_scale() < 1. {
+                difficulty += &format!(" ({:.0}% speed)", self.time_scale() * 100.);
+            }
+            if !self.spawning_enabled {
+                difficulty += " | Practice";
+            }
+            draw_hud_text(ctx, &mut canvas, Text::new(difficulty), self.hud_layout.difficulty, self.palette.hud)?;
+            draw_hud_text(ctx, &mut canvas, Text::new(format!("Score: {} (x{})\nHP: {:.0}\nBombs: {}\nWave {}: {} left", self.score, self.combo.multiplier(), self.hp, self.bombs, self.wave, self.remaining_crates())), self.hud_layout.score, self.palette.hud)?;
+
+            let crate_limit = self.crate_limit();
+            let crowded = self.crates.len() as f32 >= crate_limit as f32 * CRATE_WARNING_THRESHOLD;
+            let blink = (self.play_time / CRATE_WARNING_BLINK) as u32 % 2 == 1;
+            let text = Text::new(format!("Crates: {}/{}", self.crates.len(), crate_limit));
+            let size: Vec2 = text.measure(ctx)?.into();
+            let (anchor, offset) = self.hud_layout.crates;
+            let pos = anchor.place(offset, size);
+            let backdrop = Mesh::new_rectangle(ctx, DrawMode::fill(), Rect::new(pos.x - 4., pos.y - 2., size.x + 8., size.y + 4.), HUD_BACKDROP)?;
+            canvas.draw(&backdrop, DrawParam::new());
+            let color = if crowded && blink { self.palette.threat } else { self.palette.hud };
+            canvas.draw(&text, DrawParam::new().dest(pos).color(color));
+
+            let health = (self.hp / SHIP_HP).clamp(0., 1.);
+            let charge = 1. - (self.fire_cooldown / self.fire_interval()).clamp(0., 1.);
+            let mut bars = graphics::MeshBuilder::new();
+            for (pos, size, fill, color) in [
+                (HEALTH_BAR_POS, HEALTH_BAR_SIZE, health, health_color(health)),
+                (COOLDOWN_BAR_POS, COOLDOWN_BAR_SIZE, charge, self.palette.hud),
+            ] {
+                if fill > 0. {
+                    bars.rectangle(DrawMode::fill(), Rect::new(pos.x, pos.y, size.x * fill, size.y), color)?;
+                }
+                bars.rectangle(DrawMode::stroke(1.), Rect::new(pos.x, pos.y, size.x, size.y), self.palette.hud)?;
+            }
+            if let Some(boss) = &self.boss {
+                let fill = (boss.hp / BOSS_HP).clamp(0., 1.);
+                bars.rectangle(DrawMode::fill(), Rect::new(BOSS_BAR_POS.x, BOSS_BAR_POS.y, BOSS_BAR_SIZE.x * fill, BOSS_BAR_SIZE.y), BOSS_COLOR)?;
+                bars.rectangle(DrawMode::stroke(1.), Rect::new(BOSS_BAR_POS.x, BOSS_BAR_POS.y, BOSS_BAR_SIZE.x, BOSS_BAR_SIZE.y), self.palette.hud)?;
+            }
+            canvas.draw(&Mesh::from_data(ctx, bars.build()), DrawParam::new());
+
+            let mut minimap = graphics::MeshBuilder::new();
+            let bounds = Rect::new(MINIMAP_POS.x, MINIMAP_POS.y, MINIMAP_SIZE.x, MINIMAP_SIZE.y);
+            minimap.rectangle(DrawMode::fill(), bounds, MINIMAP_BACKGROUND)?;
+            minimap.rectangle(DrawMode::stroke(1.), bounds, self.palette.hud)?;
+            let scale = MINIMAP_SIZE / Vec2::new(WIDTH, HEIGHT);
+            let dots = self.crates.iter().map(|c| (c.pos, opacity(0.8)))
+                .chain(self.enemies.iter().map(|e| (e.obj.pos, ENEMY_COLOR)))
+                .chain(iter::once((self.ship.pos, self.palette.highlight)));
+            for (pos, color) in dots {
+                let p = MINIMAP_POS + pos.clamp(Vec2::ZERO, Vec2::new(WIDTH, HEIGHT)) * scale;
+                minimap.rectangle(DrawMode::fill(), Rect::new(p.x - 1., p.y - 1., 2., 2.), color)?;
+            }
+            canvas.draw(&Mesh::from_data(ctx, minimap.build()), DrawParam::new());
+
+            if self.wave_break > 0. {
+                draw_hud_text(ctx, &mut canvas, Text::new(format!("Wave {}", self.wave)), (Anchor::Center, Vec2::new(0., -40.)), self.palette.hud)?;
+            }
+            if self.paused {
+                draw_hud_text(ctx, &mut canvas, Text::new("PAUSED"), (Anchor::Center, Vec2::ZERO), self.palette.hud)?;
+            }
+            if self.diagnostics {
+                let text = format!(
+                    "fps: {:.0}\nbullets: {}\ncrates: {}/{}\nsplinters: {}\nspawn timer: {:.2}",
+                    ctx.time.fps(), self.bullets.len(), self.crates.len(), self.crate_limit(), self.splinters.len(), self.crate_spawn_time,
+                );
+                draw_hud_text(ctx, &mut canvas, Text::new(text), self.hud_layout.diagnostics, self.palette.hud)?;
+            }
+            match self.scene {
+                Scene::Menu => {
+                    let text = Text::new(format!(
+                        "Shooty\nHigh Score: {}\nDifficulty: {} ({:?} to change)\n{:?} for settings\nPress Enter to start",
+                        self.high_scores.get(self.difficulty, self.panic_slow), self.difficulty.name(), self.controls.cycle_difficulty, self.controls.open_settings,
+                    ));
+                    draw_hud_text(ctx, &mut canvas, text, (Anchor::Center, Vec2::ZERO), self.palette.hud)?;
+                }
+                Scene::Settings => {
+                    let mut text = String::from("Settings\n");
+                    for (i, row) in SettingsRow::ALL.into_iter().enumerate() {
+                        let cursor = if i == self.settings_row { ">" } else { " " };
+                        let line = match row {
+                            SettingsRow::Volume => format!("Volume: {:.0}%", self.config.volume * 100.),
+                            SettingsRow::Difficulty => format!("Difficulty: {}", self.config.difficulty.name()),
+                            SettingsRow::Bounce => format!("Bounce edges: {}", if self.config.bounce { "On" } else { "Off" }),
+                            SettingsRow::Density => format!("Density: {:.2}x", self.config.density),
+                        };
+                        text += &format!("{cursor} {line}\n");
+                    }
+                    text += "\nUp/Down to select, Left/Right to change\nEnter or Escape to return";
+                    draw_hud_text(ctx, &mut canvas, Text::new(text), (Anchor::Center, Vec2::ZERO), self.palette.hud)?;
+                }
+                Scene::GameOver => {
+                    let panic = if self.panic_run { ", panic slow" } else { "" };
+                    let text = Text::new(format!(
+                        "Game Over\nHigh Score ({}{panic}): {}\nPress Enter to restart",
+                        self.run_difficulty.name(), self.high_scores.get(self.run_difficulty, self.panic_run),
+                    ));
+                    draw_hud_text(ctx, &mut canvas, text, (Anchor::Center, Vec2::ZERO), self.palette.threat)?;
+                }
+                Scene::Playing => (),
+            }
+        }
+
+        canvas.finish(ctx)?;
+
+        if let Some(shader) = crt_shader {
+            let mut canvas = graphics::Canvas::from_frame(ctx, Color::BLACK);
+            canvas.set_shader(shader);
+            canvas.draw(&self.scene_img.image(ctx), DrawParam::new());
+            canvas.finish(ctx)?;
+        }
+
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            match screenshot(ctx) {
+                Ok(path) => println!("saved screenshot to {}", path.display()),
+                Err(e) => eprintln!("could not save screenshot: {e}"),
+            }
+        }
+        Ok(())
+    }
+
+    fn key_down_event(&mut self, _ctx: &mut Context, _input: KeyInput, _repeated: bool) -> GameResult {
+        Ok(())
+    }
+
+    fn quit_event(&mut self, ctx: &mut Context) -> GameResult<bool> {
+        match self.save_recording(ctx) {
+            Ok(Some(path)) => println!("wrote replay to {}", path.display()),
+            Ok(None) => (),
+            Err(e) => eprintln!("could not write replay: {e}"),
+        }
+        if let Err(e) = self.save_high_score(ctx) {
+            eprintln!("could not save high score: {e}");
+        }
+        Ok(false)
+    }
+
+    fn mouse_button_down_event(&mut self, ctx: &mut Context, button: MouseButton, _x: f32, _y: f32) -> GameResult {
+        if self.debug && button == MouseButton::Left {
+            let cursor = self.to_world(self.mouse_pos(ctx));
+            let candidates = iter::once((Selection::Ship, &self.ship))
+                .chain(self.bullets.iter().enumerate().map(|(i, b)| (Selection::Bullet(i), &b.obj)))
+                .chain(self.crates.iter().enumerate().map(|(i, c)| (Selection::Crate(i), c)))
+                .chain(self.splinters.iter().enumerate().map(|(i, s)| (Selection::Splinter(i), &s.obj)));
+            self.selected = candidates
+                .map(|(sel, obj)| (sel, (obj.pos - cursor).length_squared()))
+                .filter(|&(_, dist_sq)| dist_sq < PICK_DIST * PICK_DIST)
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(sel, _)| sel);
+        }
+        Ok(())
+    }
+
+    fn focus_event(&mut self, _ctx: &mut Context, gained: bool) -> GameResult {
+        if !gained && self.scene == Scene::Playing && !self.paused {
+            self.paused = true;
+            self.focus_paused = true;
+        } else if gained && self.focus_paused {
+            self.focus_paused = false;
+            self.paused = !self.auto_resume;
+        }
+        Ok(())
+    }
+
+    fn resize_event(&mut self, _ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        if width >= 1. && height >= 1. {
+            self.window = Vec2::new(width, height);
+        }
+        Ok(())
+    }
+
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) -> GameResult {
+        self.zoom = (self.zoom * ZOOM_STEP.powf(y)).clamp(ZOOM_MIN, ZOOM_MAX);
+        Ok(())
+    }
+}
+
+fn crate_color(crat: &Obj, light: f32) -> Color {
+    let base = if crat.hp > 1 { TOUGH_CRATE_COLOR } else { Color::WHITE };
+    let t = (crat.flash / CRATE_FLASH_TIME).clamp(0., 1.);
+    Color::new(
+        base.r + (CRATE_FLASH_COLOR.r - base.r) * t,
+        base.g + (CRATE_FLASH_COLOR.g - base.g) * t,
+        base.b + (CRATE_FLASH_COLOR.b - base.b) * t,
+        light * (crat.age / CRATE_FADE_IN_TIME).min(1.),
+    )
+}
+
+fn health_color(health: f32) -> Color {
+    let (from, to, t) = if health > 0.5 {
+        (Color::YELLOW, Color::GREEN, 2. * health - 1.)
+    } else {
+        (Color::RED, Color::YELLOW, 2. * health)
+    };
+    Color::new(
+        from.r + (to.r - from.r) * t,
+        from.g + (to.g - from.g) * t,
+        from.b + (to.b - from.b) * t,
+        1.,
+    )
+}
+
+fn fade_alpha(ttl: f32, fade_time: f32) -> f32 {
+    (ttl / fade_time).clamp(0., 1.)
+}
+
+const fn opacity(a: f32) -> Color {
+    Color {
+        a,
+        .. Color::WHITE
+    }
+}
+
+const WIDTH: f32 = 1200.;
+const HEIGHT: f32 = 900.;
+
+pub fn main() -> GameResult {
+    let Some(args) = Args::parse(std::env::args().skip(1)) else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    let cb = ggez::ContextBuilder::new("shooty", "Falch")
+        .window_setup(WindowSetup::default().title("Shooty"))
+        .window_mode(window_mode(&Config::DEFAULT, false))
+    ;
+    let (mut ctx, event_loop) = cb.build()?;
+
+    #[cfg(debug_assertions)]
+    {
+        // Add the workspace directory to the filesystem when running with cargo
+        if let Ok(manifest_dir) = ::std::env::var("CARGO_MANIFEST_DIR") {
+            let mut path = ::std::path::PathBuf::from(manifest_dir);
+            path.push("resources");
+            ctx.fs.mount(&path, true);
+        }
+    }
+
+    let mut config = Config::load(&ctx);
+    config.width = args.width.unwrap_or(config.width);
+    config.height = args.height.unwrap_or(config.height);
+    config.difficulty = args.difficulty.unwrap_or(config.difficulty);
+    config.bounce |= args.bounce;
+    let config = config.validated();
+    if (config.width, config.height) != (WIDTH, HEIGHT) {
+        ctx.gfx.set_mode(window_mode(&config, false))?;
+    }
+    let state = MainState::new(&ctx, config, args)?;
+    event::run(ctx, event_loop, state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    // Counts allocations made by the current thread while `COUNTING` is set
+    struct CountingAlloc;
+
+    thread_local! {
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+        static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if COUNTING.with(Cell::get) {
+                ALLOCATIONS.with(|n| n.set(n.get() + 1));
+            }
+            unsafe { System.alloc(layout) }
+        }
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            if COUNTING.with(Cell::get) {
+                ALLOCATIONS.with(|n| n.set(n.get() + 1));
+            }
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOC: CountingAlloc = CountingAlloc;
+
+    #[test]
+    fn bullet_ttl_within_bounds() {
+        let ship = Obj::new(0.5 * WIDTH, 0.5 * HEIGHT);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let bullet = shoot(&mut rng, &ship, ship.rot, BULLET_SPEED);
+            assert!((BULLET_TTL_MIN ..= BULLET_TTL_MAX).contains(&bullet.ttl));
+        }
+    }
+
+    #[test]
+    fn bullet_and_splinter_churn() {
+        // Rapid triple shot, 15 kills a second and a screen-clearing burst every 5 s
+        let mut rng = StdRng::seed_from_u64(1);
+        let ship = Obj::new(0.5 * WIDTH, 0.5 * HEIGHT);
+        let (mut bullets, mut splinters) = (Vec::new(), Vec::new());
+        let mut counts = Vec::new();
+        for second in 0..70 {
+            ALLOCATIONS.with(|n| n.set(0));
+            COUNTING.with(|c| c.set(true));
+            for step in 0..60 {
+                if step % 4 == 0 {
+                    for spread in [-0.1, 0., 0.1] {
+                        bullets.push(shoot(&mut rng, &ship, spread, BULLET_SPEED));
+                    }
+                }
+                let kills = if step == 0 && second % 5 == 0 { CRATE_LIMIT } else { (step % 4 == 1) as usize };
+                for _ in 0..kills {
+                    shatter(&mut rng, &mut splinters, SPLINTERS_PER_CRATE, Obj::new(100., 100.), Vec2::ZERO);
+                }
+                expire(&mut rng, &mut bullets, DELTA);
+                expire(&mut rng, &mut splinters, DELTA);
+            }
+            COUNTING.with(|c| c.set(false));
+            counts.push(ALLOCATIONS.with(Cell::get));
+        }
+        // Only the first seconds grow the backing storage; after that every push reuses it
+        assert!(counts[..5].iter().sum::<u64>() > 0);
+        assert_eq!(counts[5..].iter().sum::<u64>(), 0, "allocations per second: {counts:?}");
+    }
+
+    #[test]
+    fn bullet_fade_curve() {
+        assert_eq!(fade_alpha(6., 0.5), 1.);
+        assert_eq!(fade_alpha(0.5, 0.5), 1.);
+        assert_eq!(fade_alpha(0.25, 0.5), 0.5);
+        assert_eq!(fade_alpha(0., 0.5), 0.);
+        assert_eq!(fade_alpha(-0.1, 0.5), 0.);
+        assert_eq!(fade_alpha(0.5, 1.), 0.5);
+    }
+
+    #[test]
+    fn expire_removes_only_dead_bullets() {
+        let ttls = [0.5, 0.01, 2., 0.02, 1.];
+        let mut bullets: Vec<_> = ttls.iter().map(|&ttl| Obj::new(ttl, 0.).bullet(ttl)).collect();
+
+        let mut rng = StdRng::seed_from_u64(1);
+        expire(&mut rng, &mut bullets, 0.1);
+
+        let left: Vec<_> = bullets.iter().map(|b| b.obj.pos.x).collect();
+        assert_eq!(left, [0.5, 2., 1.]);
+        for bullet in &bullets {
+            assert!((bullet.ttl - (bullet.obj.pos.x - 0.1)).abs() < 1e-6);
+        }
+
+        let mut clusters = vec![Obj::new(0., 0.).cluster(0.05)];
+        expire(&mut rng, &mut clusters, 0.1);
+        assert_eq!(clusters.len(), CLUSTER_CHILDREN);
+    }
+
+    #[test]
+    fn splinters_are_capped() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut splinters = Vec::new();
+        for _ in 0..SPLINTER_LIMIT {
+            shatter(&mut rng, &mut splinters, SPLINTERS_PER_CRATE, Obj::new(100., 100.), Vec2::ZERO);
+        }
+        assert_eq!(splinters.len(), SPLINTER_LIMIT);
+
+        expire(&mut rng, &mut splinters, 5.);
+        assert!(splinters.is_empty());
+    }
+
+    #[test]
+    fn initial_snapshot_is_empty() {
+        let save = Snapshot::initial(7, &Config::DEFAULT, Difficulty::Hard);
+        assert!(save.bullets.is_empty() && save.missiles.is_empty() && save.enemy_bullets.is_empty());
+        assert!(save.crates.is_empty() && save.enemies.is_empty() && save.boss.is_none());
+        assert!(save.splinters.is_empty() && save.magnets.is_empty() && save.power_ups.is_empty());
+        assert_eq!(save.ship.pos, Vec2::new(0.5 * WIDTH, 0.5 * HEIGHT));
+        assert_eq!(save.ship.vel, Vec2::ZERO);
+        assert_eq!((save.step, save.score, save.wave, save.hp), (0, 0, 1, SHIP_HP));
+    }
+
+    #[test]
+    fn full_destruction_leaves_no_halves() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut crates = vec![Obj::new(100., 100.), Obj::new(400., 300.), Obj::new(600., 100.)];
+        let (mut splinters, mut power_ups) = (Vec::new(), Vec::new());
+        let points = destroy_crate_fully(&mut rng, &mut crates, &mut splinters, SPLINTERS_PER_CRATE, &mut power_ups, 0, Vec2::X, false);
+        assert_eq!(points, 2 * CRATE_POINTS);
+        assert_eq!(crates.len(), 2);
+        assert!(crates.iter().all(|c| c.size == FULL_SIZE && c.pos.x > 300.));
+    }
+
+    #[test]
+    fn config_falls_back_to_defaults() {
+        let config: Config = toml::from_str("bullet_speed = 600.0\nrot_speed = -1.0\ncrate_limit = 0").unwrap();
+        let config = config.validated();
+        assert_eq!(config.bullet_speed, 600.);
+        assert_eq!(config.rot_speed, ROT_SPEED);
+        assert_eq!(config.crate_limit, CRATE_LIMIT);
+        assert_eq!(config.acceleration, ACCELERATION);
+
+        let config: Config = toml::from_str("volume = 3.0\nspawn_distribution = \"Ring\"\ndifficulty = \"Hard\"\nbounce = true\ndensity = 9.0").unwrap();
+        let config = config.validated();
+        assert_eq!(config.volume, MASTER_VOLUME);
+        assert_eq!(config.density, DENSITY);
+        assert_eq!(config.difficulty, Difficulty::Hard);
+        assert_eq!(config.spawn_distribution, SpawnDistribution::Ring);
+        assert!(config.bounce);
+        assert_eq!(toml::from_str::<Config>(&toml::to_string(&config).unwrap()).unwrap(), config);
+        assert!(toml::from_str::<Config>("difficulty = \"Brutal\"").is_err());
+    }
+
+    #[test]
+    fn args_parse_and_ignore_malformed() {
+        let args = |list: &[&str]| Args::parse(list.iter().map(|s| s.to_string()));
+        let parsed = args(&["--seed", "42", "--width", "wide", "--difficulty", "HARD", "--bounce"]).unwrap();
+        assert_eq!(parsed, Args { width: None, height: None, seed: Some(42), difficulty: Some(Difficulty::Hard), bounce: true, record: false, replay: false });
+        assert_eq!(args(&["--difficulty", "brutal"]).unwrap().difficulty, None);
+        assert_eq!(args(&["--height"]).unwrap().height, None);
+        assert_eq!(args(&["--help"]), None);
+    }
+
+    #[test]
+    fn replay_round_trips() {
+        let mut replay = Replay::new(42, Difficulty::Hard, Input::TOGGLE_BOUNCE | Input::TOGGLE_SPAWNING, Config { bullet_speed: 600., crate_limit: 50, spawn_distribution: SpawnDistribution::Ring, density: 2.5, ..Config::DEFAULT });
+        replay.inputs = vec![Input(0), Input(Input::FORWARD | Input::FIRE), Input(Input::BOMB)];
+        let mut out = Vec::new();
+        replay.write(&mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(Replay::parse(&text), Some(replay));
+        assert_eq!(Replay::parse(&format!("shooty-replay {REPLAY_VERSION} 42 Hard 0\n1 2 3 4 5 6 Uniform 1\nzz")), None);
+        assert_eq!(Replay::parse("shooty-replay 0 42 Hard 0\n1 2 3 4 5 6"), None);
+    }
+
+    #[test]
+    fn high_scores_round_trip() {
+        let mut scores = HighScores::default();
+        scores.set(Difficulty::Hard, true, 120);
+        scores.set(Difficulty::Easy, false, 30);
+        let mut out = Vec::new();
+        scores.write(&mut out).unwrap();
+        assert_eq!(HighScores::parse(&String::from_utf8(out).unwrap()), scores);
+        assert_eq!(HighScores::parse("77\n").get(Difficulty::Normal, false), 77);
+    }
+
+    #[test]
+    fn wrapped_delta_across_seam() {
+        let a = Vec2::new(2., 100.);
+        let b = Vec2::new(WIDTH - 3., 100.);
+        assert!((wrapped_delta(a, b) - Vec2::new(5., 0.)).length() < 1e-3);
+        assert!((wrapped_delta(b, a) - Vec2::new(-5., 0.)).length() < 1e-3);
+
+        let c = Vec2::new(100., HEIGHT - 1.);
+        let d = Vec2::new(100., 4.);
+        assert!((wrapped_delta(c, d) - Vec2::new(0., -5.)).length() < 1e-3);
+
+        let mut left = Obj::new(2., 100.);
+        let mut right = Obj::new(WIDTH - 3., 100.);
+        assert!(left.resolve(&mut right, false).is_none());
+        assert!(left.resolve(&mut right, true).is_some());
+        assert_eq!(bullet_hit(&Obj::new(2., 100.), &[Obj::new(WIDTH - 3., 100.)], true), Some(0));
+    }
+
+    #[test]
+    #[cfg(not(feature = "gravity"))]
+    fn head_on_collision_exchanges_velocity() {
+        let mut a = Obj::with(100., 100., 40., 0., 0., 0.);
+        let mut b = Obj::with(120., 100., -40., 0., 0., 0.);
+        assert!(a.resolve(&mut b, false).is_some());
+        assert_eq!(a.vel, Vec2::new(-40., 0.));
+        assert_eq!(b.vel, Vec2::new(40., 0.));
+        assert_eq!(a.pos.x + b.pos.x, 220.);
+    }
+
+    #[test]
+    #[cfg(not(feature = "gravity"))]
+    fn separated_objects_are_untouched() {
+        let (a0, b0) = (Obj::with(100., 100., 40., 0., 0., 0.), Obj::with(140., 100., -40., 0., 0., 0.));
+        let (mut a, mut b) = (a0, b0);
+        assert!(a.resolve(&mut b, false).is_none());
+        assert_eq!((a, b), (a0, b0));
+    }
+
+    #[test]
+    #[cfg(not(feature = "gravity"))]
+    fn overlapping_objects_are_pushed_apart() {
+        let mut a = Obj::new(100., 100.);
+        let mut b = Obj::new(110., 100.);
+        a.resolve(&mut b, false);
+        assert!(((b.pos - a.pos).length() - (a.radius + b.radius)).abs() < 1e-4);
+        assert_eq!(a.pos.x + b.pos.x, 210.);
+        assert_eq!((a.vel, b.vel), (Vec2::ZERO, Vec2::ZERO));
+    }
+
+    #[test]
+    #[cfg(not(feature = "gravity"))]
+    fn collisions_conserve_momentum() {
+        let mut ship = Obj { mass: SHIP_MASS, ..Obj::with(100., 100., 50., 0., 0., 0.) };
+        let mut crat = Obj::with(120., 100., -80., 0., 0., 0.);
+        let momentum = |a: &Obj, b: &Obj| a.mass * a.vel + b.mass * b.vel;
+
+        let before = momentum(&ship, &crat);
+        assert!(ship.resolve(&mut crat, false).is_some());
+        assert!((momentum(&ship, &crat) - before).length() < 1e-3);
+        assert!((ship.vel.x - 50.).abs() < (crat.vel.x + 80.).abs());
+
+        let mut crat = Obj::with(130., 100., 30., 0., 0., 0.);
+        let mut wall = Obj { mass: 0., ..Obj::new(150., 100.) };
+        assert!(crat.resolve(&mut wall, false).is_some());
+        assert_eq!(wall.pos, Vec2::new(150., 100.));
+        assert_eq!(wall.vel, Vec2::ZERO);
+        assert!((crat.vel.x + 30.).abs() < 1e-3);
+        assert!((crat.pos.x - 118.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn grid_matches_brute_force() {
+        use self_compare::SliceCompareExt;
+
+        let scene = [
+            Obj::with(100., 100., 20., 0., 0., 0.),
+            Obj::with(120., 110., -20., 5., 0., 0.),
+            Obj::with(130., 90., 0., 0., 0., 0.),
+            Obj::with(300., 300., 0., 0., 0., 0.),
+            Obj::with(318., 325., 0., -10., 0., 0.),
+            Obj::with(31., 31., 0., 0., 0., 0.),
+            Obj::with(33., 33., 0., 0., 0., 0.),
+            Obj::with(600., 400., 0., 0., 0., 0.),
+        ];
+
+        let mut brute = scene;
+        brute.compare_self_mut(|a, b| {a.resolve(b, false);});
+        let mut grid = scene;
+        resolve_crates(&mut grid, false);
+
+        for (b, g) in brute.iter().zip(&grid) {
+            assert_eq!(b.pos, g.pos);
+            assert_eq!(b.vel, g.vel);
+        }
+        assert_ne!(scene[0].pos, grid[0].pos);
+    }
+
+    #[test]
+    fn grid_within_covers_radius() {
+        let objs: Vec<_> = (0..40).map(|i| Obj::new((i * 37 % 29) as f32 * 30., (i * 13 % 17) as f32 * 35.)).collect();
+        for wrap in [false, true] {
+            let grid = Grid::new(&objs, wrap);
+            for centre in [Vec2::new(5., 5.), Vec2::new(400., 300.), Vec2::new(WIDTH - 3., HEIGHT - 40.)] {
+                let inside = |&i: &usize| {
+                    let d = if wrap { wrapped_delta(objs[i].pos, centre) } else { objs[i].pos - centre };
+                    d.length_squared() < BLAST_RADIUS * BLAST_RADIUS
+                };
+                let mut found: Vec<_> = grid.within(centre, BLAST_RADIUS).filter(inside).collect();
+                found.sort_unstable();
+                let brute: Vec<_> = (0..objs.len()).filter(inside).collect();
+                assert_eq!(found, brute);
+            }
+        }
+    }
+
+    #[test]
+    fn tough_crates_survive_hits() {
+        let mut crat = Obj { hp: TOUGH_CRATE_HP, ..Obj::new(100., 100.) };
+        for _ in 1..TOUGH_CRATE_HP {
+            assert!(crat.hit(Vec2::new(500., 0.)));
+        }
+        assert!(crat.vel.x > 0.);
+        assert!(crat.flash > 0.);
+        assert!(!crat.hit(Vec2::new(500., 0.)));
+        assert!(!Obj::new(0., 0.).hit(Vec2::ZERO));
+    }
+
+    #[test]
+    fn stalls_clamp_delta() {
+        assert_eq!(clamp_delta(Duration::from_millis(16)), 0.016);
+        assert_eq!(clamp_delta(Duration::from_secs(30)), MAX_FRAME_DELTA);
+
+        let mut obj = Obj::from(Vec2::ZERO, Vec2::new(500., 0.), 0.);
+        obj.pos += obj.vel * clamp_delta(Duration::from_secs(30));
+        assert!(obj.pos.x <= 500. * MAX_FRAME_DELTA);
+    }
+
+    #[test]
+    fn substeps_catch_fast_bullets() {
+        let crates = [Obj::new(100., 100.)];
+        let start = Obj::from(Vec2::new(60., 100.), Vec2::new(80. * 60., 0.), 0.);
+        let run = |sub_stepping| {
+            let mut bullet = start;
+            let mut hit = None;
+            for_substeps(DELTA, start.vel.length(), sub_stepping, |dt| {
+                integrate(&mut bullet, dt, false, false);
+                hit = hit.or(bullet_hit(&bullet, &crates, false));
+            });
+            hit
+        };
+        assert_eq!(run(false), None);
+        assert_eq!(run(true), Some(0));
+    }
+
+    #[test]
+    fn magnetic_edge_keeps_speed() {
+        let mut obj = Obj::from(Vec2::new(WIDTH - 100., HEIGHT - 100.), Vec2::new(100., 60.), 0.);
+        let mut wrapped = false;
+        let mut steps = 0;
+        while obj.pos.x < 100. || !wrapped {
+            wrapped |= integrate(&mut obj, DELTA, false, true).is_some();
+            steps += 1;
+        }
+        assert_eq!(obj.vel, Vec2::new(100., 60.));
+        assert!(steps < 120, "the boost should shorten the crossing, took {steps} steps");
+    }
+
+    #[test]
+    fn swept_hit_catches_tunneling() {
+        let crates = [Obj { radius: 4., ..Obj::new(100., 100.) }];
+        let mut bullet = Obj::from(Vec2::new(60., 100.), Vec2::new(80. * 60., 0.), 0.).bullet(1.);
+        bullet.obj.pos += bullet.obj.vel / 60.;
+        assert!(bullet.obj.pos.x > 100. + 4. + BULLET_RADIUS);
+        assert_eq!(bullet_hit(&bullet.obj, &crates, false), None);
+        assert_eq!(swept_hit(&bullet, &crates, false), Some(0));
+
+        let mut wrapped = Obj::from(Vec2::new(WIDTH - 2., 100.), Vec2::new(BULLET_SPEED, 0.), 0.).bullet(1.);
+        wrapped.obj.pos = Vec2::new(6., 100.);
+        assert_eq!(swept_hit(&wrapped, &[Obj { radius: 1., ..Obj::new(1., 100.) }], true), Some(0));
+        assert_eq!(swept_hit(&wrapped, &[Obj { radius: 1., ..Obj::new(WIDTH / 2., 100.) }], true), None);
+    }
+
+    #[test]
+    fn spawn_distributions() {
+        let ship = Vec2::new(0.5 * WIDTH, 0.5 * HEIGHT);
+        let in_bounds = |p: Vec2| (0. .. WIDTH).contains(&p.x) && (0. .. HEIGHT).contains(&p.y);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let p = SpawnDistribution::Uniform.sample(&mut rng, ship);
+            assert!(in_bounds(p));
+
+            let p = SpawnDistribution::EdgeBiased.sample(&mut rng, ship);
+            assert!(in_bounds(p));
+            let edge_dist = p.x.min(WIDTH - p.x).min(p.y).min(HEIGHT - p.y);
+            assert!(edge_dist <= SPAWN_EDGE_BAND);
+
+            let p = SpawnDistribution::Ring.sample(&mut rng, ship);
+            assert!(in_bounds(p));
+            assert!(((p - ship).length() - SPAWN_RING_RADIUS).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn seeded_spawns_repeat() {
+        let ship = Vec2::new(0.5 * WIDTH, 0.5 * HEIGHT);
+        let spawns = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..20).map(|_| SPAWN_DISTRIBUTION.sample(&mut rng, ship)).collect::<Vec<_>>()
+        };
+        assert_eq!(spawns(42), spawns(42));
+        assert_ne!(spawns(42), spawns(43));
+    }
+
+    #[test]
+    fn damped_bounces_lose_speed() {
+        let mut ball = Obj::with(0.5 * WIDTH, 0.5 * HEIGHT, 900., 0., 0., 0.);
+        let mut speeds = Vec::new();
+        for _ in 0..600 {
+            ball.pos += ball.vel / 60.;
+            let before = ball.vel.x;
+            ball.bounce_edges(0.8);
+            if ball.vel.x.signum() != before.signum() {
+                speeds.push(ball.vel.length());
+            }
+        }
+        assert!(speeds.len() >= 3);
+        assert!(speeds.windows(2).all(|w| w[1] < w[0]));
+    }
 }